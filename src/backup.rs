@@ -33,6 +33,7 @@ impl BkpExt for BKP {
             },
             tamper: Tamper { _ownership: () },
             octl: OCTL { _ownership: () },
+            write_access: WriteAccess { _ownership: () },
         }
     }
 }
@@ -51,6 +52,10 @@ pub struct Parts {
     ///
     /// Constrains `BKP_OCTL`.
     pub octl: OCTL,
+    /// Write access to the backup domain
+    ///
+    /// Proves that `PMU_CTL`'s BKPWEN is set, e.g. for the RTC.
+    pub write_access: WriteAccess,
 }
 
 // verified on GD32VF103C-START board; 2020-03-16
@@ -197,9 +202,57 @@ pub struct OCTL {
     _ownership: (),
 }
 
+/// Write access to the backup domain, enabled by `split`
+pub struct WriteAccess {
+    _ownership: (),
+}
+
+/// RTC pulse output on the TAMPER pin
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RtcOutput {
+    /// Pulse on each RTC alarm
+    Alarm,
+    /// Pulse on each RTC second
+    Second,
+}
+
 impl OCTL {
-    // todo: use this register
-    // pub(crate) fn octl(&mut self) -> &bkp::OCTL {
-    //     unsafe { &(*BKP::ptr()).octl }
-    // }
+    #[inline]
+    pub(crate) fn octl(&mut self) -> &bkp::OCTL {
+        unsafe { &(*BKP::ptr()).octl }
+    }
+
+    /// Set the RTC clock calibration value (RCCV, 0 to 127).
+    ///
+    /// The RTC clock is slowed down by skipping `value` pulses out of every
+    /// 2^20 pulses.
+    ///
+    /// Ref: Section 4.4.2, the User Manual
+    pub fn set_calibration(&mut self, value: u8) {
+        assert!(value < 128);
+        self.octl().modify(|_, w| unsafe { w.rccv().bits(value) });
+    }
+
+    /// Output the RTC clock divided by 64 on the TAMPER pin (COEN), for
+    /// measuring the calibration.
+    pub fn enable_clock_output(&mut self) {
+        self.octl().modify(|_, w| w.coen().set_bit());
+    }
+
+    /// Stop the RTC clock output on the TAMPER pin.
+    pub fn disable_clock_output(&mut self) {
+        self.octl().modify(|_, w| w.coen().clear_bit());
+    }
+
+    /// Output a pulse on the TAMPER pin on RTC alarm or second events
+    /// (ASOEN and ROSEL).
+    pub fn enable_pulse_output(&mut self, output: RtcOutput) {
+        self.octl()
+            .modify(|_, w| w.rosel().bit(output == RtcOutput::Second).asoen().set_bit());
+    }
+
+    /// Stop the RTC pulse output on the TAMPER pin.
+    pub fn disable_pulse_output(&mut self) {
+        self.octl().modify(|_, w| w.asoen().clear_bit());
+    }
 }
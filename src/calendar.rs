@@ -0,0 +1,250 @@
+//! Calendar on the RTC counter
+//!
+//! The RTC counter is taken as seconds since the Unix epoch, 1970-01-01
+//! 00:00:00 UTC, which covers dates until 2106-02-07 06:28:15. Leap seconds
+//! are not counted, as in Unix time.
+//!
+//! Conversions use the proleptic Gregorian calendar: years divisible by 4
+//! are leap years, except those divisible by 100 but not by 400.
+use crate::rtc::Rtc;
+
+/// Day of the week
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl Weekday {
+    // 1970-01-01 is a Thursday
+    fn from_days(days: u32) -> Weekday {
+        match (days + 3) % 7 {
+            0 => Weekday::Monday,
+            1 => Weekday::Tuesday,
+            2 => Weekday::Wednesday,
+            3 => Weekday::Thursday,
+            4 => Weekday::Friday,
+            5 => Weekday::Saturday,
+            _ => Weekday::Sunday,
+        }
+    }
+}
+
+/// Date and time of day
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DateTime {
+    /// Year, 1970 to 2106
+    pub year: u16,
+    /// Month, 1 to 12
+    pub month: u8,
+    /// Day of the month, 1 to 31
+    pub day: u8,
+    /// Hour, 0 to 23
+    pub hour: u8,
+    /// Minute, 0 to 59
+    pub minute: u8,
+    /// Second, 0 to 59
+    pub second: u8,
+    /// Day of the week; ignored when converting into a timestamp
+    pub weekday: Weekday,
+}
+
+/// Error of date and time values out of range
+#[derive(Debug)]
+pub struct InvalidDateTime;
+
+/// Checks if `year` is a leap year
+// `is_multiple_of` needs a newer compiler than the one this crate supports
+#[allow(clippy::manual_is_multiple_of)]
+pub fn is_leap_year(year: u16) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+/// Number of days in the month of the year, or 0 for invalid months
+pub fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        _ => 0,
+    }
+}
+
+// Days from 1970-01-01 to the date, which should be valid and not earlier.
+// Years start from March here, so that the leap day is the last day of year.
+fn days_from_date(year: u16, month: u8, day: u8) -> u32 {
+    let year = year as u32 - if month <= 2 { 1 } else { 0 };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let month_from_march = (month as u32 + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day as u32 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    // 719_468 days from 0000-03-01 to 1970-01-01
+    era * 146_097 + day_of_era - 719_468
+}
+
+// Date (year, month, day) of the count of days from 1970-01-01
+fn date_from_days(days: u32) -> (u16, u8, u8) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+    (year as u16, month as u8, day as u8)
+}
+
+impl DateTime {
+    /// Creates a date and time, with the weekday calculated; returns `None`
+    /// if any value is out of range
+    pub fn new(
+        year: u16,
+        month: u8,
+        day: u8,
+        hour: u8,
+        minute: u8,
+        second: u8,
+    ) -> Option<DateTime> {
+        let mut date_time = DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+            weekday: Weekday::Thursday,
+        };
+        let timestamp = date_time.timestamp().ok()?;
+        date_time.weekday = Weekday::from_days(timestamp / 86_400);
+        Some(date_time)
+    }
+
+    /// Converts seconds since the Unix epoch into date and time
+    pub fn from_timestamp(timestamp: u32) -> DateTime {
+        let days = timestamp / 86_400;
+        let seconds = timestamp % 86_400;
+        let (year, month, day) = date_from_days(days);
+        DateTime {
+            year,
+            month,
+            day,
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8,
+            weekday: Weekday::from_days(days),
+        }
+    }
+
+    /// Converts into seconds since the Unix epoch
+    pub fn timestamp(&self) -> Result<u32, InvalidDateTime> {
+        if self.year < 1970
+            || self.year > 2106
+            || self.day == 0
+            || self.day > days_in_month(self.year, self.month)
+            || self.hour > 23
+            || self.minute > 59
+            || self.second > 59
+        {
+            return Err(InvalidDateTime);
+        }
+        let days = days_from_date(self.year, self.month, self.day) as u64;
+        let seconds =
+            days * 86_400 + self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64;
+        if seconds > u32::MAX as u64 {
+            return Err(InvalidDateTime);
+        }
+        Ok(seconds as u32)
+    }
+}
+
+impl Rtc {
+    /// Reads the counter as date and time
+    pub fn date_time(&self) -> DateTime {
+        DateTime::from_timestamp(self.counter())
+    }
+
+    /// Sets the counter to the date and time
+    pub fn set_date_time(&mut self, date_time: &DateTime) -> Result<(), InvalidDateTime> {
+        let timestamp = date_time.timestamp()?;
+        self.set_counter(timestamp);
+        Ok(())
+    }
+
+    /// Sets the alarm at the date and time
+    pub fn set_alarm_at(&mut self, date_time: &DateTime) -> Result<(), InvalidDateTime> {
+        let timestamp = date_time.timestamp()?;
+        self.set_alarm(timestamp);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Checks both directions of the conversion
+    fn check(timestamp: u32, date_time: (u16, u8, u8, u8, u8, u8), weekday: Weekday) {
+        let (year, month, day, hour, minute, second) = date_time;
+        let expected = DateTime::new(year, month, day, hour, minute, second).unwrap();
+        assert_eq!(expected.weekday, weekday);
+        assert_eq!(DateTime::from_timestamp(timestamp), expected);
+        assert_eq!(expected.timestamp().unwrap(), timestamp);
+    }
+
+    #[test]
+    fn epoch() {
+        check(0, (1970, 1, 1, 0, 0, 0), Weekday::Thursday);
+    }
+
+    #[test]
+    fn leap_days() {
+        check(951_782_400, (2000, 2, 29, 0, 0, 0), Weekday::Tuesday);
+        check(951_868_800, (2000, 3, 1, 0, 0, 0), Weekday::Wednesday);
+        check(1_709_210_096, (2024, 2, 29, 12, 34, 56), Weekday::Thursday);
+        // 2100 is not a leap year
+        check(4_107_542_399, (2100, 2, 28, 23, 59, 59), Weekday::Sunday);
+        check(4_107_542_400, (2100, 3, 1, 0, 0, 0), Weekday::Monday);
+    }
+
+    #[test]
+    fn signed_32_bit_overflow() {
+        check(2_147_483_647, (2038, 1, 19, 3, 14, 7), Weekday::Tuesday);
+        check(2_147_483_648, (2038, 1, 19, 3, 14, 8), Weekday::Tuesday);
+    }
+
+    #[test]
+    fn counter_limit() {
+        check(u32::MAX, (2106, 2, 7, 6, 28, 15), Weekday::Sunday);
+        assert!(DateTime::new(2106, 2, 7, 6, 28, 16).is_none());
+        assert!(DateTime::new(2106, 12, 31, 0, 0, 0).is_none());
+    }
+
+    #[test]
+    fn invalid_date_times() {
+        assert!(DateTime::new(1969, 12, 31, 23, 59, 59).is_none());
+        assert!(DateTime::new(2107, 1, 1, 0, 0, 0).is_none());
+        assert!(DateTime::new(2021, 0, 1, 0, 0, 0).is_none());
+        assert!(DateTime::new(2021, 13, 1, 0, 0, 0).is_none());
+        assert!(DateTime::new(2021, 1, 0, 0, 0, 0).is_none());
+        assert!(DateTime::new(2021, 4, 31, 0, 0, 0).is_none());
+        assert!(DateTime::new(2021, 2, 29, 0, 0, 0).is_none());
+        assert!(DateTime::new(2100, 2, 29, 0, 0, 0).is_none());
+        assert!(DateTime::new(2021, 1, 1, 24, 0, 0).is_none());
+        assert!(DateTime::new(2021, 1, 1, 0, 60, 0).is_none());
+        assert!(DateTime::new(2021, 1, 1, 0, 0, 60).is_none());
+    }
+}
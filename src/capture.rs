@@ -0,0 +1,431 @@
+//! Input capture and PWM input
+//!
+//! TIMER0 to TIMER4 could capture the counter value on edges of channel 0 to
+//! 3 inputs. The counter runs freely at the resolution frequency, so the time
+//! between two captures is the difference of captured values.
+//!
+//! `PwmInput` pairs channel 0 and 1 on the channel 0 pin to measure period
+//! and pulse width of a PWM signal.
+//!
+//! Pins should be configured into `Input<Floating>` mode. Pin mapping and
+//! remaps are the same as in the `pwm` module, whose pin traits are shared
+//! with the `Input<Floating>` pin mode.
+//!
+//! Ref: Section 15, the User Manual
+use crate::afio::PCF0;
+use crate::gpio::{Floating, Input};
+use crate::pac::afio::pcf0;
+use crate::pac::{TIMER0, TIMER1, TIMER2, TIMER3, TIMER4};
+use crate::pwm::{remap_bits, Ch0, Pins};
+use crate::rcu::{Clocks, APB1, APB2};
+use crate::unit::{Hertz, MicroSeconds};
+
+pub use crate::pwm::{Channel, NoPin};
+
+/// Input capture errors
+#[derive(Debug)]
+pub enum Error {
+    /// A new value was captured before the last one was read
+    Overcapture,
+}
+
+/// Input edge to capture on
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    Rising,
+    Falling,
+}
+
+/// Capture prescaler; captures once every N edges
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Prescaler {
+    Div1,
+    Div2,
+    Div4,
+    Div8,
+}
+
+/// Capture config
+pub struct Config {
+    pub edge: Edge,
+    /// Digital filter of the input, CHxCAPFLT, 0 (off) to 15
+    pub filter: u8,
+    pub prescaler: Prescaler,
+    /// Counting frequency of the counter
+    pub resolution: Hertz,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            edge: Edge::Rising,
+            filter: 0,
+            prescaler: Prescaler::Div1,
+            resolution: Hertz(1_000_000),
+        }
+    }
+}
+
+impl Config {
+    pub fn edge(mut self, edge: Edge) -> Self {
+        self.edge = edge;
+        self
+    }
+
+    pub fn filter(mut self, filter: u8) -> Self {
+        assert!(filter < 16);
+        self.filter = filter;
+        self
+    }
+
+    pub fn prescaler(mut self, prescaler: Prescaler) -> Self {
+        self.prescaler = prescaler;
+        self
+    }
+
+    pub fn resolution(mut self, resolution: impl Into<Hertz>) -> Self {
+        self.resolution = resolution.into();
+        self
+    }
+
+    #[inline]
+    fn prescaler_bits(&self) -> u8 {
+        match self.prescaler {
+            Prescaler::Div1 => 0b00,
+            Prescaler::Div2 => 0b01,
+            Prescaler::Div4 => 0b10,
+            Prescaler::Div8 => 0b11,
+        }
+    }
+}
+
+// CHxMS: input, CIx mapped on its own pin (01) or the paired pin (10)
+const CAPTURE_DIRECT: u8 = 0b01;
+const CAPTURE_INDIRECT: u8 = 0b10;
+
+// Overcapture flags in INTF; cleared by writing 0, other flags written as 1
+const CH0OF: u16 = 1 << 9;
+const CH1OF: u16 = 1 << 10;
+const CH2OF: u16 = 1 << 11;
+const CH3OF: u16 = 1 << 12;
+
+// Prescaler value to count at `resolution` with the timer clock `clock`
+fn resolution_psc(clock: Hertz, resolution: Hertz) -> u16 {
+    let resolution = u32::max(resolution.0, 1);
+    let div = (clock.0 + resolution / 2) / resolution;
+    (u32::min(u32::max(div, 1), 1 << 16) - 1) as u16
+}
+
+/// Input capture on all four channels of a timer
+pub struct Capture<TIMER, PINS> {
+    timer: TIMER,
+    pins: PINS,
+    clock_frequency: Hertz,
+}
+
+/// PWM input on channel 0 of a timer
+///
+/// Channel 0 captures the period and channel 1 the pulse width, both from
+/// the channel 0 pin; the counter is reset on every period start edge.
+/// Periods longer than 65536 counts of the resolution could not be measured.
+pub struct PwmInput<TIMER, PIN> {
+    timer: TIMER,
+    pin: PIN,
+    clock_frequency: Hertz,
+}
+
+macro_rules! capture {
+    ($($TIMERX:ident: (
+        $timerx:ident, $timerxen:ident, $timerxrst:ident, $APBX:ident, $ck_timerx:ident,
+        $set_remap:expr
+    ),)+) => {
+$(
+impl<PINS> Capture<$TIMERX, PINS> {
+    /// Power on the timer and configure all four channels into input
+    /// capture mode, with the counter running at the configured resolution.
+    ///
+    /// Channels are disabled until enabled by `try_enable`.
+    pub fn $timerx(
+        timer: $TIMERX,
+        pins: PINS,
+        pcf0: &mut PCF0,
+        config: Config,
+        clocks: Clocks,
+        apb: &mut $APBX,
+    ) -> Self
+    where
+        PINS: Pins<$TIMERX, Input<Floating>>,
+    {
+        let remap = remap_bits(&PINS::REMAPS);
+        riscv::interrupt::free(|_| {
+            apb.en().modify(|_, w| w.$timerxen().set_bit());
+            apb.rst().modify(|_, w| w.$timerxrst().set_bit());
+            apb.rst().modify(|_, w| w.$timerxrst().clear_bit());
+            pcf0.pcf0().modify(|_, w| {
+                ($set_remap)(w, remap);
+                w
+            });
+        });
+        let (filter, psc) = (config.filter, config.prescaler_bits());
+        timer.chctl0_input().write(|w| unsafe {
+            w.ch0ms().bits(CAPTURE_DIRECT).ch0capflt().bits(filter).ch0cappsc().bits(psc)
+                .ch1ms().bits(CAPTURE_DIRECT).ch1capflt().bits(filter).ch1cappsc().bits(psc)
+        });
+        timer.chctl1_input().write(|w| unsafe {
+            w.ch2ms().bits(CAPTURE_DIRECT).ch2capflt().bits(filter).ch2cappsc().bits(psc)
+                .ch3ms().bits(CAPTURE_DIRECT).ch3capflt().bits(filter).ch3cappsc().bits(psc)
+        });
+        let falling = config.edge == Edge::Falling;
+        timer.chctl2.write(|w| {
+            w.ch0p().bit(falling).ch1p().bit(falling)
+                .ch2p().bit(falling).ch3p().bit(falling)
+        });
+        let mut capture = Capture {
+            timer,
+            pins,
+            clock_frequency: clocks.$ck_timerx(),
+        };
+        embedded_hal::capture::Capture::try_set_resolution(&mut capture, config.resolution).ok();
+        capture.timer.car.write(|w| unsafe { w.carl().bits(u16::MAX) });
+        capture.timer.ctl0.modify(|_, w| w.cen().set_bit());
+        capture
+    }
+
+    /// Power down the timer and return ownership of owned registers
+    pub fn release(self, apb: &mut $APBX) -> ($TIMERX, PINS) {
+        self.timer.ctl0.modify(|_, w| w.cen().clear_bit());
+        apb.en().modify(|_, w| w.$timerxen().clear_bit());
+        (self.timer, self.pins)
+    }
+
+    /// Time between two captured values, with the counter wrapped at most
+    /// once between them
+    pub fn duration(&self, from: u16, to: u16) -> MicroSeconds {
+        let ticks = to.wrapping_sub(from) as u64;
+        let resolution = self.resolution().0 as u64;
+        MicroSeconds((ticks * 1_000_000 / resolution) as u32)
+    }
+
+    /// Frequency of a signal whose consecutive edges were captured as
+    /// `from` and `to`
+    pub fn frequency(&self, from: u16, to: u16) -> Hertz {
+        let ticks = u32::max(to.wrapping_sub(from) as u32, 1);
+        Hertz(self.resolution().0 / ticks)
+    }
+
+    #[inline]
+    fn resolution(&self) -> Hertz {
+        let psc = self.timer.psc.read().psc().bits() as u32;
+        Hertz(self.clock_frequency.0 / (psc + 1))
+    }
+}
+
+impl<PINS> embedded_hal::capture::Capture for Capture<$TIMERX, PINS> {
+    type Error = Error;
+    type Channel = Channel;
+    type Time = Hertz;
+    type Capture = u16;
+
+    /// Reads the counter value captured on the last edge
+    fn try_capture(&mut self, channel: Channel) -> nb::Result<u16, Self::Error> {
+        let intf = self.timer.intf.read();
+        let (captured, overcaptured) = match channel {
+            Channel::C0 => (intf.ch0if().bit_is_set(), intf.ch0of().bit_is_set()),
+            Channel::C1 => (intf.ch1if().bit_is_set(), intf.ch1of().bit_is_set()),
+            Channel::C2 => (intf.ch2if().bit_is_set(), intf.ch2of().bit_is_set()),
+            Channel::C3 => (intf.ch3if().bit_is_set(), intf.ch3of().bit_is_set()),
+        };
+        if !captured {
+            return Err(nb::Error::WouldBlock);
+        }
+        // reading the capture value clears CHxIF
+        let value = match channel {
+            Channel::C0 => self.timer.ch0cv.read().ch0val().bits(),
+            Channel::C1 => self.timer.ch1cv.read().ch1val().bits(),
+            Channel::C2 => self.timer.ch2cv.read().ch2val().bits(),
+            Channel::C3 => self.timer.ch3cv.read().ch3val().bits(),
+        };
+        if overcaptured {
+            let flag = match channel {
+                Channel::C0 => CH0OF,
+                Channel::C1 => CH1OF,
+                Channel::C2 => CH2OF,
+                Channel::C3 => CH3OF,
+            };
+            self.timer.intf.write(|w| unsafe { w.bits(!flag) });
+            return Err(nb::Error::Other(Error::Overcapture));
+        }
+        Ok(value)
+    }
+
+    fn try_disable(&mut self, channel: Channel) -> Result<(), Self::Error> {
+        self.timer.chctl2.modify(|_, w| match channel {
+            Channel::C0 => w.ch0en().clear_bit(),
+            Channel::C1 => w.ch1en().clear_bit(),
+            Channel::C2 => w.ch2en().clear_bit(),
+            Channel::C3 => w.ch3en().clear_bit(),
+        });
+        Ok(())
+    }
+
+    fn try_enable(&mut self, channel: Channel) -> Result<(), Self::Error> {
+        self.timer.chctl2.modify(|_, w| match channel {
+            Channel::C0 => w.ch0en().set_bit(),
+            Channel::C1 => w.ch1en().set_bit(),
+            Channel::C2 => w.ch2en().set_bit(),
+            Channel::C3 => w.ch3en().set_bit(),
+        });
+        Ok(())
+    }
+
+    /// Counting frequency of the counter
+    fn try_get_resolution(&self) -> Result<Hertz, Self::Error> {
+        Ok(self.resolution())
+    }
+
+    fn try_set_resolution<R>(&mut self, resolution: R) -> Result<(), Self::Error>
+    where
+        R: Into<Hertz>,
+    {
+        let psc = resolution_psc(self.clock_frequency, resolution.into());
+        riscv::interrupt::free(|_| {
+            self.timer.psc.write(|w| unsafe { w.psc().bits(psc) });
+            // load the prescaler; this also resets the counter
+            self.timer.swevg.write(|w| w.upg().set_bit());
+        });
+        Ok(())
+    }
+}
+
+impl<PIN> PwmInput<$TIMERX, PIN> {
+    /// Power on the timer and measure the PWM signal on the channel 0 pin,
+    /// with the counter running at the configured resolution.
+    ///
+    /// The configured edge starts a period; the pulse width is measured
+    /// until the opposite edge.
+    pub fn $timerx(
+        timer: $TIMERX,
+        pin: PIN,
+        pcf0: &mut PCF0,
+        config: Config,
+        clocks: Clocks,
+        apb: &mut $APBX,
+    ) -> Self
+    where
+        PIN: Ch0<$TIMERX, Input<Floating>>,
+    {
+        let remap = remap_bits(&[PIN::REMAP]);
+        riscv::interrupt::free(|_| {
+            apb.en().modify(|_, w| w.$timerxen().set_bit());
+            apb.rst().modify(|_, w| w.$timerxrst().set_bit());
+            apb.rst().modify(|_, w| w.$timerxrst().clear_bit());
+            pcf0.pcf0().modify(|_, w| {
+                ($set_remap)(w, remap);
+                w
+            });
+        });
+        let (filter, psc) = (config.filter, config.prescaler_bits());
+        timer.chctl0_input().write(|w| unsafe {
+            w.ch0ms().bits(CAPTURE_DIRECT).ch0capflt().bits(filter).ch0cappsc().bits(psc)
+                .ch1ms().bits(CAPTURE_INDIRECT).ch1capflt().bits(filter).ch1cappsc().bits(psc)
+        });
+        let falling = config.edge == Edge::Falling;
+        timer.chctl2.write(|w| {
+            w.ch0p().bit(falling).ch0en().set_bit()
+                .ch1p().bit(!falling).ch1en().set_bit()
+        });
+        // slave mode: restart the counter on filtered CI0 (CI0FE0) edges
+        timer.smcfg.write(|w| unsafe { w.trgs().bits(0b101).smc().bits(0b100) });
+        let clock_frequency = clocks.$ck_timerx();
+        let psc = resolution_psc(clock_frequency, config.resolution);
+        timer.psc.write(|w| unsafe { w.psc().bits(psc) });
+        timer.car.write(|w| unsafe { w.carl().bits(u16::MAX) });
+        timer.swevg.write(|w| w.upg().set_bit());
+        timer.ctl0.modify(|_, w| w.cen().set_bit());
+        PwmInput {
+            timer,
+            pin,
+            clock_frequency,
+        }
+    }
+
+    /// Power down the timer and return ownership of owned registers
+    pub fn release(self, apb: &mut $APBX) -> ($TIMERX, PIN) {
+        self.timer.ctl0.modify(|_, w| w.cen().clear_bit());
+        apb.en().modify(|_, w| w.$timerxen().clear_bit());
+        (self.timer, self.pin)
+    }
+
+    /// Counting frequency of the counter
+    pub fn resolution(&self) -> Hertz {
+        let psc = self.timer.psc.read().psc().bits() as u32;
+        Hertz(self.clock_frequency.0 / (psc + 1))
+    }
+
+    /// Reads `(pulse_width, period)` in counts of the resolution, once a
+    /// period has been measured
+    pub fn read_duty(&mut self) -> nb::Result<(u16, u16), Error> {
+        if self.timer.intf.read().ch0if().bit_is_clear() {
+            return Err(nb::Error::WouldBlock);
+        }
+        let width = self.timer.ch1cv.read().ch1val().bits();
+        // reading the capture value clears CH0IF
+        let period = self.timer.ch0cv.read().ch0val().bits();
+        if self.timer.intf.read().ch0of().bit_is_set() {
+            self.timer.intf.write(|w| unsafe { w.bits(!(CH0OF | CH1OF)) });
+            return Err(nb::Error::Other(Error::Overcapture));
+        }
+        Ok((width, period))
+    }
+
+    /// Reads the frequency of the input signal
+    pub fn read_frequency(&mut self) -> nb::Result<Hertz, Error> {
+        let (_, period) = self.read_duty()?;
+        Ok(Hertz(self.resolution().0 / u32::max(period as u32, 1)))
+    }
+
+    /// Reads the pulse width of the input signal
+    pub fn read_pulse_width(&mut self) -> nb::Result<MicroSeconds, Error> {
+        let (width, _) = self.read_duty()?;
+        let us = width as u64 * 1_000_000 / self.resolution().0 as u64;
+        Ok(MicroSeconds(us as u32))
+    }
+}
+)+
+    };
+}
+
+capture! {
+    TIMER0: (timer0, timer0en, timer0rst, APB2, ck_timerx,
+        |w: &mut pcf0::W, bits| unsafe { w.timer0_remap().bits(bits); }),
+    TIMER1: (timer1, timer1en, timer1rst, APB1, ck_timerx_apb1,
+        |w: &mut pcf0::W, bits| unsafe { w.timer1_remap().bits(bits); }),
+    TIMER2: (timer2, timer2en, timer2rst, APB1, ck_timerx_apb1,
+        |w: &mut pcf0::W, bits| unsafe { w.timer2_remap().bits(bits); }),
+    TIMER3: (timer3, timer3en, timer3rst, APB1, ck_timerx_apb1,
+        |w: &mut pcf0::W, bits| { w.timer3_remap().bit(bits != 0); }),
+    TIMER4: (timer4, timer4en, timer4rst, APB1, ck_timerx_apb1,
+        |_: &mut pcf0::W, _| {}),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolution_prescaler() {
+        assert_eq!(resolution_psc(Hertz(108_000_000), Hertz(1_000_000)), 107);
+        assert_eq!(resolution_psc(Hertz(108_000_000), Hertz(108_000_000)), 0);
+        // rounded to the nearest division
+        assert_eq!(resolution_psc(Hertz(108_000_000), Hertz(7_000_000)), 14);
+    }
+
+    #[test]
+    fn resolution_prescaler_clamped() {
+        // resolutions above the timer clock count at the timer clock
+        assert_eq!(resolution_psc(Hertz(108_000_000), Hertz(200_000_000)), 0);
+        // resolutions too low for the prescaler count as slow as possible
+        assert_eq!(resolution_psc(Hertz(108_000_000), Hertz(1)), 0xFFFF);
+        assert_eq!(resolution_psc(Hertz(108_000_000), Hertz(0)), 0xFFFF);
+    }
+}
@@ -0,0 +1,420 @@
+//! Output compare and one-pulse mode
+//!
+//! `OutputCompare` runs the counter of TIMER0 to TIMER4 over a period and
+//! changes channel outputs when the counter matches the compare value of a
+//! channel. It splits into `CompareChannel`s, which only accept compare
+//! modes, and a `SplitCompare` keeping the timer and pins; joining them
+//! back gives the `OutputCompare` to release. A timer owned by
+//! `OutputCompare` could not be used for PWM at the same time.
+//!
+//! `OnePulse` outputs a single pulse on channel 0 with a delay after being
+//! triggered and a width; the counter stops by itself after the pulse
+//! (single pulse mode, SPM).
+//!
+//! Pins should be configured into `Alternate<PushPull>` mode, see the `pwm`
+//! module for valid pins and remaps.
+//!
+//! Ref: Section 15.1.4, the User Manual
+use crate::afio::PCF0;
+use crate::pac::afio::pcf0;
+use crate::pac::{TIMER0, TIMER1, TIMER2, TIMER3, TIMER4};
+use crate::pwm::{remap_bits, Ch0, Pins, C0, C1, C2, C3};
+use crate::rcu::{Clocks, APB1, APB2};
+use crate::timer::{calc_psc_car, Period, UPIF};
+use crate::unit::{Hertz, MicroSeconds};
+use core::marker::PhantomData;
+
+/// Output compare mode of a channel (CHxCOMCTL)
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CompareMode {
+    /// Output is not changed on match; the match flag is still set
+    Frozen,
+    /// Output goes active on match
+    ActiveOnMatch,
+    /// Output goes inactive on match
+    InactiveOnMatch,
+    /// Output toggles on every match
+    Toggle,
+    /// Output is forced inactive
+    ForceInactive,
+    /// Output is forced active
+    ForceActive,
+}
+
+impl CompareMode {
+    #[inline]
+    fn bits(self) -> u8 {
+        match self {
+            CompareMode::Frozen => 0b000,
+            CompareMode::ActiveOnMatch => 0b001,
+            CompareMode::InactiveOnMatch => 0b010,
+            CompareMode::Toggle => 0b011,
+            CompareMode::ForceInactive => 0b100,
+            CompareMode::ForceActive => 0b101,
+        }
+    }
+}
+
+// PWM mode 1: output is active while the counter is not less than the
+// compare value
+const PWM_MODE_1: u8 = 0b111;
+
+/// Output compare abstraction of a timer
+pub struct OutputCompare<TIMER, PINS> {
+    timer: TIMER,
+    pins: PINS,
+    clock_frequency: Hertz,
+}
+
+/// A single output compare channel, split from `OutputCompare`
+pub struct CompareChannel<TIMER, CH> {
+    _timer: PhantomData<TIMER>,
+    _channel: PhantomData<CH>,
+}
+
+/// Timer and pins of an `OutputCompare` while split into channels
+pub struct SplitCompare<TIMER, PINS> {
+    compare: OutputCompare<TIMER, PINS>,
+}
+
+/// Single pulse output on channel 0 of a timer
+pub struct OnePulse<TIMER, PIN> {
+    timer: TIMER,
+    pin: PIN,
+    clock_frequency: Hertz,
+}
+
+// Calculate (psc, car, cv) for a pulse after `delay` and lasting `width`
+// timer clock cycles; both the delay and the width are at least one count
+fn one_pulse_registers(delay: u64, width: u64) -> (u16, u16, u16) {
+    const MAX: u64 = 1 << 16;
+    let delay = u64::max(delay, 1);
+    let total = delay + u64::max(width, 1);
+    let div = u64::min((total - 1) / MAX + 1, MAX);
+    let cnt = u64::min((total + div / 2) / div, MAX);
+    let cv = u64::min(u64::max((delay + div / 2) / div, 1), cnt - 1);
+    ((div - 1) as u16, (cnt - 1) as u16, cv as u16)
+}
+
+macro_rules! compare {
+    ($($TIMERX:ident: (
+        $timerx:ident, $timerxen:ident, $timerxrst:ident, $APBX:ident, $ck_timerx:ident,
+        $set_remap:expr $(, $cchp:ident)?
+    ),)+) => {
+$(
+impl<PINS> OutputCompare<$TIMERX, PINS> {
+    /// Power on the timer and start counting over `period`. All channels
+    /// are in `Frozen` mode and disabled.
+    pub fn $timerx(
+        timer: $TIMERX,
+        pins: PINS,
+        pcf0: &mut PCF0,
+        period: impl Into<Period>,
+        clocks: Clocks,
+        apb: &mut $APBX,
+    ) -> Self
+    where
+        PINS: Pins<$TIMERX>,
+    {
+        let remap = remap_bits(&PINS::REMAPS);
+        riscv::interrupt::free(|_| {
+            apb.en().modify(|_, w| w.$timerxen().set_bit());
+            apb.rst().modify(|_, w| w.$timerxrst().set_bit());
+            apb.rst().modify(|_, w| w.$timerxrst().clear_bit());
+            pcf0.pcf0().modify(|_, w| {
+                ($set_remap)(w, remap);
+                w
+            });
+        });
+        // advanced timers need the primary output enabled
+        $( timer.$cchp.modify(|_, w| w.poen().set_bit()); )?
+        let mut compare = OutputCompare {
+            timer,
+            pins,
+            clock_frequency: clocks.$ck_timerx(),
+        };
+        compare.set_period(period);
+        compare.timer.ctl0.modify(|_, w| w.arse().set_bit().cen().set_bit());
+        compare
+    }
+
+    /// Power down the timer and return ownership of owned registers
+    pub fn release(self, apb: &mut $APBX) -> ($TIMERX, PINS) {
+        self.timer.ctl0.modify(|_, w| w.cen().clear_bit());
+        apb.en().modify(|_, w| w.$timerxen().clear_bit());
+        (self.timer, self.pins)
+    }
+
+    /// Sets the period of the counter and restarts it
+    pub fn set_period(&mut self, period: impl Into<Period>) {
+        let (psc, car) = calc_psc_car(period.into().ticks(self.clock_frequency), u16::MAX);
+        riscv::interrupt::free(|_| {
+            self.timer.psc.write(|w| unsafe { w.psc().bits(psc) });
+            self.timer.car.write(|w| unsafe { w.carl().bits(car) });
+            // load the prescaler and the shadowed registers
+            self.timer.swevg.write(|w| w.upg().set_bit());
+        });
+    }
+
+    /// Counting frequency of the counter
+    pub fn resolution(&self) -> Hertz {
+        let psc = self.timer.psc.read().psc().bits() as u32;
+        Hertz(self.clock_frequency.0 / (psc + 1))
+    }
+
+    /// Returns the auto-reload value; compare values should not exceed it
+    pub fn auto_reload(&self) -> u16 {
+        self.timer.car.read().carl().bits()
+    }
+
+    /// Split into independent channels; the period could no longer be
+    /// changed until the channels are joined back
+    pub fn split(
+        self,
+    ) -> (
+        SplitCompare<$TIMERX, PINS>,
+        (
+            CompareChannel<$TIMERX, C0>,
+            CompareChannel<$TIMERX, C1>,
+            CompareChannel<$TIMERX, C2>,
+            CompareChannel<$TIMERX, C3>,
+        ),
+    ) {
+        (
+            SplitCompare { compare: self },
+            (
+                CompareChannel::<$TIMERX, C0>::new(),
+                CompareChannel::<$TIMERX, C1>::new(),
+                CompareChannel::<$TIMERX, C2>::new(),
+                CompareChannel::<$TIMERX, C3>::new(),
+            ),
+        )
+    }
+
+    /// Join split channels back, e.g. to change the period or release
+    pub fn join(
+        split: SplitCompare<$TIMERX, PINS>,
+        _channels: (
+            CompareChannel<$TIMERX, C0>,
+            CompareChannel<$TIMERX, C1>,
+            CompareChannel<$TIMERX, C2>,
+            CompareChannel<$TIMERX, C3>,
+        ),
+    ) -> Self {
+        split.compare
+    }
+}
+
+compare_channel! { $TIMERX: [
+    C0: (chctl0_output, ch0comctl, ch0comsen, ch0en, ch0cv, ch0val, ch0ie, ch0if),
+    C1: (chctl0_output, ch1comctl, ch1comsen, ch1en, ch1cv, ch1val, ch1ie, ch1if),
+    C2: (chctl1_output, ch2comctl, ch2comsen, ch2en, ch2cv, ch2val, ch2ie, ch2if),
+    C3: (chctl1_output, ch3comctl, ch3comsen, ch3en, ch3cv, ch3val, ch3ie, ch3if),
+] }
+
+impl<PIN> OnePulse<$TIMERX, PIN> {
+    /// Power on the timer and prepare a pulse on the channel 0 pin. The
+    /// output stays inactive until triggered.
+    pub fn $timerx(
+        timer: $TIMERX,
+        pin: PIN,
+        pcf0: &mut PCF0,
+        delay: impl Into<MicroSeconds>,
+        width: impl Into<MicroSeconds>,
+        clocks: Clocks,
+        apb: &mut $APBX,
+    ) -> Self
+    where
+        PIN: Ch0<$TIMERX>,
+    {
+        let remap = remap_bits(&[PIN::REMAP]);
+        riscv::interrupt::free(|_| {
+            apb.en().modify(|_, w| w.$timerxen().set_bit());
+            apb.rst().modify(|_, w| w.$timerxrst().set_bit());
+            apb.rst().modify(|_, w| w.$timerxrst().clear_bit());
+            pcf0.pcf0().modify(|_, w| {
+                ($set_remap)(w, remap);
+                w
+            });
+        });
+        timer.chctl0_output().write(|w| unsafe {
+            w.ch0comctl().bits(PWM_MODE_1).ch0comsen().set_bit()
+        });
+        $( timer.$cchp.modify(|_, w| w.poen().set_bit()); )?
+        // the counter stops at the next update event
+        timer.ctl0.write(|w| w.spm().set_bit().arse().set_bit());
+        let mut one_pulse = OnePulse {
+            timer,
+            pin,
+            clock_frequency: clocks.$ck_timerx(),
+        };
+        one_pulse.set_pulse(delay, width);
+        one_pulse.timer.chctl2.write(|w| w.ch0en().set_bit());
+        one_pulse
+    }
+
+    /// Power down the timer and return ownership of owned registers
+    pub fn release(self, apb: &mut $APBX) -> ($TIMERX, PIN) {
+        self.timer.ctl0.modify(|_, w| w.cen().clear_bit());
+        apb.en().modify(|_, w| w.$timerxen().clear_bit());
+        (self.timer, self.pin)
+    }
+
+    /// Sets delay and width of the next pulse. Both are rounded to counts
+    /// of the timer and are at least one count; a zero width still outputs
+    /// a pulse of one count.
+    pub fn set_pulse(&mut self, delay: impl Into<MicroSeconds>, width: impl Into<MicroSeconds>) {
+        let clock = self.clock_frequency.0 as u64;
+        let delay = clock * delay.into().0 as u64 / 1_000_000;
+        let width = clock * width.into().0 as u64 / 1_000_000;
+        let (psc, car, cv) = one_pulse_registers(delay, width);
+        riscv::interrupt::free(|_| {
+            self.timer.psc.write(|w| unsafe { w.psc().bits(psc) });
+            self.timer.car.write(|w| unsafe { w.carl().bits(car) });
+            self.timer.ch0cv.write(|w| unsafe { w.ch0val().bits(cv) });
+            // load the shadowed registers without starting a pulse
+            self.timer.swevg.write(|w| w.upg().set_bit());
+            self.timer.intf.write(|w| unsafe { w.bits(!UPIF) });
+        });
+    }
+
+    /// Starts a pulse; does nothing if a pulse is in progress
+    pub fn trigger(&mut self) {
+        self.timer.ctl0.modify(|_, w| w.cen().set_bit());
+    }
+
+    /// Checks if a pulse is in progress
+    pub fn is_busy(&self) -> bool {
+        self.timer.ctl0.read().cen().bit_is_set()
+    }
+}
+)+
+    };
+}
+
+macro_rules! compare_channel {
+    ($TIMERX:ident: [$($CX:ident: (
+        $chctl:ident, $chxcomctl:ident, $chxcomsen:ident, $chxen:ident,
+        $chxcv:ident, $chxval:ident, $chxie:ident, $chxif:ident
+    ),)+]) => {
+        $(
+            impl CompareChannel<$TIMERX, $CX> {
+                // callers must make sure this channel is not used elsewhere
+                fn new() -> Self {
+                    CompareChannel {
+                        _timer: PhantomData,
+                        _channel: PhantomData,
+                    }
+                }
+
+                /// Sets the output compare mode; takes effect immediately
+                pub fn set_mode(&mut self, mode: CompareMode) {
+                    riscv::interrupt::free(|_| unsafe {
+                        (*$TIMERX::ptr()).$chctl().modify(|_, w| {
+                            w.$chxcomctl().bits(mode.bits()).$chxcomsen().clear_bit()
+                        })
+                    });
+                }
+
+                /// Returns the compare value
+                pub fn compare(&self) -> u16 {
+                    unsafe { (*$TIMERX::ptr()).$chxcv.read().$chxval().bits() }
+                }
+
+                /// Sets the compare value
+                pub fn set_compare(&mut self, value: u16) {
+                    unsafe { (*$TIMERX::ptr()).$chxcv.write(|w| w.$chxval().bits(value)) };
+                }
+
+                /// Enables the channel output
+                pub fn enable(&mut self) {
+                    riscv::interrupt::free(|_| unsafe {
+                        (*$TIMERX::ptr()).chctl2.modify(|_, w| w.$chxen().set_bit())
+                    });
+                }
+
+                /// Disables the channel output
+                pub fn disable(&mut self) {
+                    riscv::interrupt::free(|_| unsafe {
+                        (*$TIMERX::ptr()).chctl2.modify(|_, w| w.$chxen().clear_bit())
+                    });
+                }
+
+                /// Enables the compare match interrupt
+                pub fn listen(&mut self) {
+                    riscv::interrupt::free(|_| unsafe {
+                        (*$TIMERX::ptr()).dmainten.modify(|_, w| w.$chxie().set_bit())
+                    });
+                }
+
+                /// Disables the compare match interrupt
+                pub fn unlisten(&mut self) {
+                    riscv::interrupt::free(|_| unsafe {
+                        (*$TIMERX::ptr()).dmainten.modify(|_, w| w.$chxie().clear_bit())
+                    });
+                }
+
+                /// Checks if the counter has matched the compare value
+                pub fn is_matched(&self) -> bool {
+                    unsafe { (*$TIMERX::ptr()).intf.read().$chxif().bit_is_set() }
+                }
+
+                /// Clears the compare match flag
+                pub fn clear_match_flag(&mut self) {
+                    // other flags are written as 1, which leaves them unchanged
+                    riscv::interrupt::free(|_| unsafe {
+                        (*$TIMERX::ptr()).intf.write(|w| w.bits(!0).$chxif().clear_bit())
+                    });
+                }
+            }
+        )+
+    };
+}
+
+compare! {
+    TIMER0: (timer0, timer0en, timer0rst, APB2, ck_timerx,
+        |w: &mut pcf0::W, bits| unsafe { w.timer0_remap().bits(bits); }, cchp),
+    TIMER1: (timer1, timer1en, timer1rst, APB1, ck_timerx_apb1,
+        |w: &mut pcf0::W, bits| unsafe { w.timer1_remap().bits(bits); }),
+    TIMER2: (timer2, timer2en, timer2rst, APB1, ck_timerx_apb1,
+        |w: &mut pcf0::W, bits| unsafe { w.timer2_remap().bits(bits); }),
+    TIMER3: (timer3, timer3en, timer3rst, APB1, ck_timerx_apb1,
+        |w: &mut pcf0::W, bits| { w.timer3_remap().bit(bits != 0); }),
+    TIMER4: (timer4, timer4en, timer4rst, APB1, ck_timerx_apb1,
+        |_: &mut pcf0::W, _| {}),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_pulse_without_prescaler() {
+        assert_eq!(one_pulse_registers(100, 50), (0, 149, 100));
+        assert_eq!(one_pulse_registers(1, 65535), (0, 65535, 1));
+    }
+
+    #[test]
+    fn one_pulse_zero_delay() {
+        assert_eq!(one_pulse_registers(0, 50), (0, 50, 1));
+    }
+
+    #[test]
+    fn one_pulse_zero_width() {
+        assert_eq!(one_pulse_registers(100, 0), (0, 100, 100));
+        assert_eq!(one_pulse_registers(0, 0), (0, 1, 1));
+    }
+
+    #[test]
+    fn one_pulse_with_prescaler() {
+        assert_eq!(one_pulse_registers(100_000, 100_000), (3, 49999, 25000));
+        // the width rounds down to zero counts but is kept at one count
+        assert_eq!(one_pulse_registers(100_001, 1), (1, 50000, 50000));
+    }
+
+    #[test]
+    fn one_pulse_saturated() {
+        assert_eq!(one_pulse_registers(1 << 40, 1 << 40), (65535, 65535, 65535));
+        assert_eq!(one_pulse_registers(1, 1 << 40), (65535, 65535, 1));
+    }
+}
@@ -1,4 +1,8 @@
-//! (TODO) Core Timer
+//! Core Timer
+//!
+//! The 64-bit core timer counts at a quarter of the system clock. It raises
+//! the timer interrupt when its value reaches the compare value, and holds
+//! the software interrupt bit.
 
 use gd32vf103_pac::CTIMER;
 
@@ -50,5 +54,86 @@ impl CoreTimer {
         self.ctimer.mtime_lo.read().bits()
     }
 
-    // todo: more functions for mtimercmp, mstop and msip.
+    /// Set the timer value
+    pub fn set_value(&mut self, value: u64) {
+        // clear the low half first so that no carry goes into the high half
+        self.ctimer.mtime_lo.write(|w| unsafe { w.bits(0) });
+        self.ctimer
+            .mtime_hi
+            .write(|w| unsafe { w.bits((value >> 32) as u32) });
+        self.ctimer
+            .mtime_lo
+            .write(|w| unsafe { w.bits(value as u32) });
+    }
+
+    /// Get the compare value, mtimecmp
+    pub fn get_compare(&self) -> u64 {
+        let hi = self.ctimer.mtimecmp_hi.read().bits();
+        let lo = self.ctimer.mtimecmp_lo.read().bits();
+        (hi as u64) << 32 | (lo as u64)
+    }
+
+    /// Set the compare value, mtimecmp
+    ///
+    /// The timer interrupt is pending while the timer value is not less than
+    /// the compare value, calling `eclic_mtip_handler` if enabled; setting a
+    /// greater compare value clears it.
+    pub fn set_compare(&mut self, value: u64) {
+        // the halves are written one by one; keep the compare value from
+        // dropping below the target in between to avoid spurious interrupts
+        self.ctimer
+            .mtimecmp_lo
+            .write(|w| unsafe { w.bits(u32::MAX) });
+        self.ctimer
+            .mtimecmp_hi
+            .write(|w| unsafe { w.bits((value >> 32) as u32) });
+        self.ctimer
+            .mtimecmp_lo
+            .write(|w| unsafe { w.bits(value as u32) });
+    }
+
+    /// Set the compare value `ticks` after the current timer value
+    ///
+    /// The sum wraps around; with a 64-bit timer this takes thousands of
+    /// years, but a compare value below the timer value fires immediately.
+    pub fn schedule_in(&mut self, ticks: u64) {
+        let value = self.get_value().wrapping_add(ticks);
+        self.set_compare(value);
+    }
+
+    /// Check if the timer interrupt is pending
+    pub fn is_compare_pending(&self) -> bool {
+        self.get_value() >= self.get_compare()
+    }
+
+    /// Stop the timer from counting (MSTOP)
+    pub fn pause(&mut self) {
+        self.ctimer.mstop.write(|w| w.timestop().set_bit());
+    }
+
+    /// Let the timer count again (MSTOP)
+    pub fn resume(&mut self) {
+        self.ctimer.mstop.write(|w| w.timestop().clear_bit());
+    }
+
+    /// Check if the timer is stopped
+    pub fn is_paused(&self) -> bool {
+        self.ctimer.mstop.read().timestop().bit_is_set()
+    }
+
+    /// Trigger the software interrupt (MSIP), which calls
+    /// `eclic_msip_handler` if enabled
+    pub fn set_software_interrupt(&mut self) {
+        self.ctimer.msip.write(|w| w.msip().set_bit());
+    }
+
+    /// Clear the software interrupt; call this in `eclic_msip_handler`
+    pub fn clear_software_interrupt(&mut self) {
+        self.ctimer.msip.write(|w| w.msip().clear_bit());
+    }
+
+    /// Check if the software interrupt is pending
+    pub fn is_software_interrupt_pending(&self) -> bool {
+        self.ctimer.msip.read().msip().bit_is_set()
+    }
 }
@@ -1,8 +1,13 @@
 //! Delays
+//!
+//! `Delay` waits on the core timer, which counts at a quarter of the system
+//! clock. For waits shorter than a microsecond, `delay_cycles` busy-waits on
+//! the `mcycle` counter, which counts every system clock cycle.
 use crate::ctimer::CoreTimer;
+use crate::monotonic::CORE_TIMER_DIVIDER;
 use crate::rcu::Clocks;
 use crate::unit::*;
-use embedded_hal::blocking::delay::DelayMs;
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
 use core::convert::Infallible;
 
 /// CoreTimer as delay provider
@@ -50,3 +55,102 @@ impl DelayMs<u32> for Delay {
         Ok(())
     }
 }
+
+impl DelayUs<u32> for Delay {
+    type Error = Infallible;
+    fn try_delay_us(&mut self, us: u32) -> Result<(), Self::Error> {
+        let count = us_to_ticks(self.clock_frequency, us);
+        let tmp: u64 = self.ctimer.get_value();
+        let mut start: u64 = self.ctimer.get_value();
+        // start on a tick edge, so the delay is never shorter than requested
+        while start == tmp {
+            start = self.ctimer.get_value();
+        }
+        while u64::wrapping_sub(self.ctimer.get_value(), start) < count {}
+        Ok(())
+    }
+}
+
+impl DelayUs<u16> for Delay {
+    type Error = Infallible;
+    fn try_delay_us(&mut self, us: u16) -> Result<(), Self::Error> {
+        self.try_delay_us(us as u32)
+    }
+}
+
+impl Delay {
+    /// Busy waits for at least `ns` nanoseconds, counting system clock
+    /// cycles; see `delay_cycles`
+    pub fn delay_ns(&mut self, ns: u32) {
+        delay_cycles(ns_to_cycles(self.clock_frequency, ns));
+    }
+}
+
+/// Number of core timer ticks in `us` microseconds with the system clock
+/// `ck_sys`, rounded up
+pub fn us_to_ticks(ck_sys: Hertz, us: u32) -> u64 {
+    // the core timer counts at ck_sys / CORE_TIMER_DIVIDER
+    (us as u64 * ck_sys.0 as u64).div_ceil(1_000_000 * CORE_TIMER_DIVIDER as u64)
+}
+
+/// Number of system clock cycles in `ns` nanoseconds with the system clock
+/// `ck_sys`, rounded up and saturated
+pub fn ns_to_cycles(ck_sys: Hertz, ns: u32) -> u32 {
+    let cycles = (ns as u64 * ck_sys.0 as u64).div_ceil(1_000_000_000);
+    u64::min(cycles, u32::MAX as u64) as u32
+}
+
+/// Busy waits for at least `cycles` system clock cycles using the `mcycle`
+/// counter.
+///
+/// A few cycles are spent on reading the counter, so this is precise to
+/// several cycles. The counter should not be stopped in `mcountinhibit`.
+#[inline]
+pub fn delay_cycles(cycles: u32) {
+    let start = riscv::register::mcycle::read() as u32;
+    while (riscv::register::mcycle::read() as u32).wrapping_sub(start) < cycles {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn us_to_ticks_whole() {
+        // IRC8M without PLL
+        assert_eq!(us_to_ticks(Hertz(8_000_000), 0), 0);
+        assert_eq!(us_to_ticks(Hertz(8_000_000), 1), 2);
+        assert_eq!(us_to_ticks(Hertz(8_000_000), 1_000), 2_000);
+        // PLL at 72 MHz and 108 MHz
+        assert_eq!(us_to_ticks(Hertz(72_000_000), 1), 18);
+        assert_eq!(us_to_ticks(Hertz(108_000_000), 1), 27);
+        assert_eq!(
+            us_to_ticks(Hertz(108_000_000), u32::MAX),
+            u32::MAX as u64 * 27
+        );
+    }
+
+    #[test]
+    fn us_to_ticks_rounded_up() {
+        // HXTAL of 25 MHz without PLL: 6.25 ticks per microsecond
+        assert_eq!(us_to_ticks(Hertz(25_000_000), 1), 7);
+        assert_eq!(us_to_ticks(Hertz(25_000_000), 3), 19);
+        assert_eq!(us_to_ticks(Hertz(25_000_000), 4), 25);
+    }
+
+    #[test]
+    fn ns_to_cycles_rounded_up() {
+        assert_eq!(ns_to_cycles(Hertz(8_000_000), 0), 0);
+        assert_eq!(ns_to_cycles(Hertz(8_000_000), 125), 1);
+        assert_eq!(ns_to_cycles(Hertz(8_000_000), 126), 2);
+        assert_eq!(ns_to_cycles(Hertz(108_000_000), 1), 1);
+        assert_eq!(ns_to_cycles(Hertz(108_000_000), 100), 11);
+        assert_eq!(ns_to_cycles(Hertz(108_000_000), 1_000), 108);
+        assert_eq!(ns_to_cycles(Hertz(108_000_000), u32::MAX), 463_856_468);
+    }
+
+    #[test]
+    fn ns_to_cycles_saturated() {
+        assert_eq!(ns_to_cycles(Hertz(u32::MAX), u32::MAX), u32::MAX);
+    }
+}
@@ -0,0 +1,326 @@
+//! Direct Memory Access (DMA)
+//!
+//! There are two DMA controllers on GD32VF103: DMA0 with 7 channels and DMA1
+//! with 5 channels. Each channel can only serve requests of fixed peripherals;
+//! peripheral modules in this crate take the channel struct they need and
+//! program it by themselves.
+//!
+//! Ref: Section 10, the User Manual
+
+use crate::rcu::AHB;
+use core::sync::atomic::{self, Ordering};
+
+/// Extension trait to split a DMA peripheral into independent channels
+pub trait DmaExt {
+    /// The type to split the DMA into
+    type Channels;
+
+    /// Splits the DMA peripheral into independent channels
+    fn split(self, ahb: &mut AHB) -> Self::Channels;
+}
+
+/// Transfer direction of a DMA channel
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Read from peripheral and write into memory
+    PeripheralToMemory,
+    /// Read from memory and write into peripheral
+    MemoryToPeripheral,
+}
+
+/// Transfer data width of memory or peripheral
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Width {
+    /// 8-bit data
+    Bits8,
+    /// 16-bit data
+    Bits16,
+    /// 32-bit data
+    Bits32,
+}
+
+impl Width {
+    #[inline]
+    fn bits(self) -> u8 {
+        match self {
+            Width::Bits8 => 0b00,
+            Width::Bits16 => 0b01,
+            Width::Bits32 => 0b10,
+        }
+    }
+}
+
+/// Software priority of a DMA channel
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Low priority
+    Low,
+    /// Medium priority
+    Medium,
+    /// High priority
+    High,
+    /// Ultra high priority
+    UltraHigh,
+}
+
+impl Priority {
+    #[inline]
+    fn bits(self) -> u8 {
+        match self {
+            Priority::Low => 0b00,
+            Priority::Medium => 0b01,
+            Priority::High => 0b10,
+            Priority::UltraHigh => 0b11,
+        }
+    }
+}
+
+/// DMA channel operations
+///
+/// Peripheral modules use this trait to program the channel they own.
+pub trait Channel {
+    /// Sets the peripheral register address; the peripheral address is never
+    /// incremented.
+    fn set_peripheral_address(&mut self, address: u32, width: Width);
+
+    /// Sets the memory address and if the address should increase after each
+    /// data is transferred.
+    fn set_memory_address(&mut self, address: u32, width: Width, increase: bool);
+
+    /// Sets the number of data to transfer.
+    fn set_transfer_length(&mut self, len: u16);
+
+    /// Returns the number of data remaining to transfer.
+    fn remaining(&self) -> u16;
+
+    /// Sets the transfer direction and priority of this channel.
+    fn set_direction(&mut self, direction: Direction, priority: Priority);
+
+    /// Enables the channel to start the transfer.
+    fn start(&mut self);
+
+    /// Disables the channel.
+    fn stop(&mut self);
+
+    /// Checks if the full transfer finish flag (FTFIF) is set.
+    fn is_complete(&self) -> bool;
+
+    /// Checks if the transfer error flag (ERRIF) is set.
+    fn is_error(&self) -> bool;
+
+    /// Clears all interrupt flags of this channel.
+    fn clear_flags(&mut self);
+
+    /// Enables the full transfer finish interrupt.
+    fn listen_complete(&mut self);
+
+    /// Disables the full transfer finish interrupt.
+    fn unlisten_complete(&mut self);
+}
+
+/// Transfer payloads which could be waited for by a `Transfer` handle
+pub trait TransferPayload {
+    /// Checks if the underlying transfer has finished.
+    fn is_done(&self) -> bool;
+
+    /// Stops the underlying transfer and disables the DMA requests.
+    fn stop(&mut self);
+}
+
+/// An on-going DMA transfer
+///
+/// This handle owns the buffer and the payload (which typically holds the
+/// peripheral and DMA channels) until the transfer finishes.
+pub struct Transfer<BUF, PAYLOAD> {
+    buffer: BUF,
+    payload: PAYLOAD,
+}
+
+impl<BUF, PAYLOAD: TransferPayload> Transfer<BUF, PAYLOAD> {
+    // Callers should have started the transfer; use `start_fence` before
+    // enabling the DMA channel.
+    pub(crate) fn new(buffer: BUF, payload: PAYLOAD) -> Self {
+        Transfer { buffer, payload }
+    }
+
+    /// Checks if the transfer has finished.
+    pub fn is_done(&self) -> bool {
+        self.payload.is_done()
+    }
+
+    /// Blocks until the transfer finishes, returns the buffer and the payload.
+    pub fn wait(mut self) -> (BUF, PAYLOAD) {
+        while !self.payload.is_done() {}
+        // buffer must not be read before the DMA transfer ends
+        atomic::compiler_fence(Ordering::Acquire);
+        self.payload.stop();
+        (self.buffer, self.payload)
+    }
+}
+
+// Buffer contents must be visible in memory before the DMA channel starts.
+#[inline]
+pub(crate) fn start_fence() {
+    atomic::compiler_fence(Ordering::Release);
+}
+
+// Prepare a channel to move bytes between memory and a peripheral data
+// register; the caller starts the channel afterwards.
+#[inline]
+pub(crate) fn setup_channel<CH: Channel>(
+    channel: &mut CH,
+    data_address: u32,
+    memory_address: u32,
+    len: usize,
+    direction: Direction,
+) {
+    assert!(len <= u16::MAX as usize, "buffer too long for DMA");
+    channel.stop();
+    channel.clear_flags();
+    channel.set_peripheral_address(data_address, Width::Bits8);
+    channel.set_memory_address(memory_address, Width::Bits8, true);
+    channel.set_transfer_length(len as u16);
+    channel.set_direction(direction, Priority::Medium);
+    start_fence();
+}
+
+macro_rules! dma {
+    ($DMAX:ident, $dmax:ident, $dmaxen:ident, [
+        $($CX:ident: ($chx:ident, $chxctl:ident, $chxcnt:ident, $chxpaddr:ident, $chxmaddr:ident,
+            $ftfifx:ident, $errifx:ident, $gifcx:ident),)+
+    ]) => {
+/// DMA controller channels
+pub mod $dmax {
+    use super::{Channel, Direction, DmaExt, Priority, Width};
+    use crate::pac::$DMAX;
+    use crate::rcu::AHB;
+
+    /// DMA channels
+    #[non_exhaustive]
+    pub struct Channels {
+        $(
+            /// Channel
+            pub $chx: $CX,
+        )+
+    }
+
+    impl DmaExt for $DMAX {
+        type Channels = Channels;
+
+        fn split(self, ahb: &mut AHB) -> Channels {
+            ahb.en().modify(|_, w| w.$dmaxen().set_bit());
+            // reset all channels to a known state
+            $(
+                self.$chxctl.reset();
+            )+
+            Channels {
+                $(
+                    $chx: $CX { _ownership: () },
+                )+
+            }
+        }
+    }
+
+$(
+    /// DMA channel
+    pub struct $CX {
+        _ownership: (),
+    }
+
+    impl Channel for $CX {
+        fn set_peripheral_address(&mut self, address: u32, width: Width) {
+            let dma = unsafe { &*$DMAX::ptr() };
+            dma.$chxpaddr.write(|w| unsafe { w.paddr().bits(address) });
+            dma.$chxctl
+                .modify(|_, w| unsafe { w.pnaga().clear_bit().pwidth().bits(width.bits()) });
+        }
+
+        fn set_memory_address(&mut self, address: u32, width: Width, increase: bool) {
+            let dma = unsafe { &*$DMAX::ptr() };
+            dma.$chxmaddr.write(|w| unsafe { w.maddr().bits(address) });
+            dma.$chxctl
+                .modify(|_, w| unsafe { w.mnaga().bit(increase).mwidth().bits(width.bits()) });
+        }
+
+        fn set_transfer_length(&mut self, len: u16) {
+            let dma = unsafe { &*$DMAX::ptr() };
+            dma.$chxcnt.write(|w| unsafe { w.cnt().bits(len) });
+        }
+
+        fn remaining(&self) -> u16 {
+            let dma = unsafe { &*$DMAX::ptr() };
+            dma.$chxcnt.read().cnt().bits()
+        }
+
+        fn set_direction(&mut self, direction: Direction, priority: Priority) {
+            let dma = unsafe { &*$DMAX::ptr() };
+            dma.$chxctl.modify(|_, w| unsafe {
+                w.dir()
+                    .bit(direction == Direction::MemoryToPeripheral)
+                    .m2m()
+                    .clear_bit()
+                    .cmen()
+                    .clear_bit()
+                    .prio()
+                    .bits(priority.bits())
+            });
+        }
+
+        fn start(&mut self) {
+            let dma = unsafe { &*$DMAX::ptr() };
+            dma.$chxctl.modify(|_, w| w.chen().set_bit());
+        }
+
+        fn stop(&mut self) {
+            let dma = unsafe { &*$DMAX::ptr() };
+            dma.$chxctl.modify(|_, w| w.chen().clear_bit());
+        }
+
+        fn is_complete(&self) -> bool {
+            let dma = unsafe { &*$DMAX::ptr() };
+            dma.intf.read().$ftfifx().bit_is_set()
+        }
+
+        fn is_error(&self) -> bool {
+            let dma = unsafe { &*$DMAX::ptr() };
+            dma.intf.read().$errifx().bit_is_set()
+        }
+
+        fn clear_flags(&mut self) {
+            let dma = unsafe { &*$DMAX::ptr() };
+            // note: INTC is a write-1-to-clear register, clearing GIF clears all flags
+            dma.intc.write(|w| w.$gifcx().set_bit());
+        }
+
+        fn listen_complete(&mut self) {
+            let dma = unsafe { &*$DMAX::ptr() };
+            dma.$chxctl.modify(|_, w| w.ftfie().set_bit());
+        }
+
+        fn unlisten_complete(&mut self) {
+            let dma = unsafe { &*$DMAX::ptr() };
+            dma.$chxctl.modify(|_, w| w.ftfie().clear_bit());
+        }
+    }
+)+
+}
+    };
+}
+
+dma! { DMA0, dma0, dma0en, [
+    C0: (ch0, ch0ctl, ch0cnt, ch0paddr, ch0maddr, ftfif0, errif0, gifc0),
+    C1: (ch1, ch1ctl, ch1cnt, ch1paddr, ch1maddr, ftfif1, errif1, gifc1),
+    C2: (ch2, ch2ctl, ch2cnt, ch2paddr, ch2maddr, ftfif2, errif2, gifc2),
+    C3: (ch3, ch3ctl, ch3cnt, ch3paddr, ch3maddr, ftfif3, errif3, gifc3),
+    C4: (ch4, ch4ctl, ch4cnt, ch4paddr, ch4maddr, ftfif4, errif4, gifc4),
+    C5: (ch5, ch5ctl, ch5cnt, ch5paddr, ch5maddr, ftfif5, errif5, gifc5),
+    C6: (ch6, ch6ctl, ch6cnt, ch6paddr, ch6maddr, ftfif6, errif6, gifc6),
+] }
+
+dma! { DMA1, dma1, dma1en, [
+    C0: (ch0, ch0ctl, ch0cnt, ch0paddr, ch0maddr, ftfif0, errif0, gifc0),
+    C1: (ch1, ch1ctl, ch1cnt, ch1paddr, ch1maddr, ftfif1, errif1, gifc1),
+    C2: (ch2, ch2ctl, ch2cnt, ch2paddr, ch2maddr, ftfif2, errif2, gifc2),
+    C3: (ch3, ch3ctl, ch3cnt, ch3paddr, ch3maddr, ftfif3, errif3, gifc3),
+    C4: (ch4, ch4ctl, ch4cnt, ch4paddr, ch4maddr, ftfif4, errif4, gifc4),
+] }
@@ -0,0 +1,1610 @@
+//! Inter-Integrated Circuit (I2C) bus
+//!
+//! This module provides I2C master drivers for I2C0 and I2C1, which implement
+//! blocking I2C traits from `embedded-hal`, event driven I2C slave drivers and
+//! an SMBus host layer with packet error checking.
+//!
+//! Pins available are:
+//!
+//! | I2C  | Remap | SCL  | SDA  |
+//! |:-----|:------|:-----|:-----|
+//! | I2C0 | No    | PB6  | PB7  |
+//! | I2C0 | Yes   | PB8  | PB9  |
+//! | I2C1 | No    | PB10 | PB11 |
+//!
+//! Both pins should be configured into `Alternate<OpenDrain>` mode.
+//!
+//! Every wait on the bus is bounded, so a stuck bus results in an
+//! `Error::Timeout` instead of a hang. The bus could then be freed by calling
+//! `recover` on the `I2c` instance.
+//!
+//! # DMA transfers
+//!
+//! Bulk transfers could be offloaded to DMA using `with_tx_dma` and
+//! `with_rx_dma`. Each I2C uses fixed DMA channels:
+//!
+//! | I2C  | TX channel | RX channel |
+//! |:-----|:-----------|:-----------|
+//! | I2C0 | DMA0 CH5   | DMA0 CH6   |
+//! | I2C1 | DMA0 CH3   | DMA0 CH4   |
+//!
+//! Ref: Section 17, the User Manual
+
+use crate::afio::PCF0;
+use crate::dma::{
+    self, setup_channel, Channel, Direction as DmaDirection, Transfer, TransferPayload,
+};
+use crate::gpio::gpiob::{CTL0, CTL1, PB10, PB11, PB6, PB7, PB8, PB9};
+use crate::gpio::{Alternate, OpenDrain, Output};
+use crate::pac::i2c0::{stat0, RegisterBlock};
+use crate::pac::{I2C0, I2C1};
+use crate::rcu::{Clocks, APB1};
+use crate::unit::Hertz;
+use core::hint;
+use core::ops::Deref;
+use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
+use embedded_hal::digital::{InputPin, OutputPin};
+
+/// I2C error
+#[derive(Debug)]
+pub enum Error {
+    /// Bus error, a misplaced START or STOP condition is detected (BERR)
+    Bus,
+    /// Arbitration lost in master mode (LOSTARB)
+    ArbitrationLost,
+    /// No acknowledge received after an address or data byte (AERR)
+    Acknowledge,
+    /// Overrun or underrun when clock stretching is disabled (OUERR)
+    Overrun,
+    /// SMBus timeout, SCL is held low for too long (SMBTO)
+    SmbusTimeout,
+    /// Packet error checking code mismatch (PECERR)
+    Pec,
+    /// The expected bus event did not happen in time; the bus may be stuck
+    /// and can be freed with `recover`
+    Timeout,
+}
+
+/// Duty cycle of SCL clock in fast mode
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DutyCycle {
+    /// T_low / T_high = 2
+    Ratio2to1,
+    /// T_low / T_high = 16 / 9
+    Ratio16to9,
+}
+
+/// I2C bus speed mode
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Standard mode, up to 100 kHz
+    Standard {
+        /// SCL clock frequency
+        frequency: Hertz,
+    },
+    /// Fast mode, up to 400 kHz
+    Fast {
+        /// SCL clock frequency
+        frequency: Hertz,
+        /// SCL clock duty cycle
+        duty_cycle: DutyCycle,
+    },
+}
+
+impl Mode {
+    /// Standard mode with given frequency
+    pub fn standard(frequency: impl Into<Hertz>) -> Self {
+        Mode::Standard {
+            frequency: frequency.into(),
+        }
+    }
+
+    /// Fast mode with given frequency and 2:1 duty cycle
+    pub fn fast(frequency: impl Into<Hertz>) -> Self {
+        Mode::Fast {
+            frequency: frequency.into(),
+            duty_cycle: DutyCycle::Ratio2to1,
+        }
+    }
+
+    /// Returns the SCL clock frequency
+    pub fn frequency(&self) -> Hertz {
+        match *self {
+            Mode::Standard { frequency } => frequency,
+            Mode::Fast { frequency, .. } => frequency,
+        }
+    }
+}
+
+// I2C timing register values
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) struct Timing {
+    // CTL1.I2CCLK, APB1 clock in MHz
+    i2cclk: u8,
+    // RT.RISETIME, maximum rise time in APB1 clock cycles plus one
+    risetime: u8,
+    // CKCFG.FAST
+    fast: bool,
+    // CKCFG.DTCY
+    dtcy: bool,
+    // CKCFG.CLKC
+    clkc: u16,
+}
+
+// Calculate I2C timing register values from APB1 clock.
+//
+// Standard mode: T_high = T_low = CLKC * T_pclk1, CLKC >= 4.
+// Fast mode: T_high = CLKC * T_pclk1 and T_low = 2 * CLKC * T_pclk1 (2:1), or
+// T_high = 9 * CLKC * T_pclk1 and T_low = 16 * CLKC * T_pclk1 (16:9), CLKC >= 1.
+// Maximum rise time is 1000ns for standard mode and 300ns for fast mode.
+//
+// Ref: Section 17.4.5, the User Manual
+pub(crate) fn calc_timing(pclk1: Hertz, mode: Mode) -> Timing {
+    let pclk1 = pclk1.0;
+    let i2cclk = pclk1 / 1_000_000;
+    assert!((2..=54).contains(&i2cclk), "invalid APB1 frequency for I2C");
+    match mode {
+        Mode::Standard { frequency } => {
+            assert!(
+                frequency.0 > 0 && frequency.0 <= 100_000,
+                "invalid I2C frequency"
+            );
+            let clkc = u32::max(pclk1 / (frequency.0 * 2), 4);
+            Timing {
+                i2cclk: i2cclk as u8,
+                risetime: (i2cclk + 1) as u8,
+                fast: false,
+                dtcy: false,
+                clkc: u32::min(clkc, 0xFFF) as u16,
+            }
+        }
+        Mode::Fast {
+            frequency,
+            duty_cycle,
+        } => {
+            assert!(
+                frequency.0 > 0 && frequency.0 <= 400_000,
+                "invalid I2C frequency"
+            );
+            let (dtcy, clkc) = match duty_cycle {
+                DutyCycle::Ratio2to1 => (false, pclk1 / (frequency.0 * 3)),
+                DutyCycle::Ratio16to9 => (true, pclk1 / (frequency.0 * 25)),
+            };
+            let clkc = u32::max(clkc, 1);
+            Timing {
+                i2cclk: i2cclk as u8,
+                risetime: (i2cclk * 300 / 1000 + 1) as u8,
+                fast: true,
+                dtcy,
+                clkc: u32::min(clkc, 0xFFF) as u16,
+            }
+        }
+    }
+}
+
+#[doc(hidden)]
+mod private {
+    pub trait Sealed {}
+}
+
+/// Valid I2C pin pairs (SCL, SDA)
+pub trait Pins<I2C>: private::Sealed {
+    #[doc(hidden)]
+    const REMAP: bool;
+}
+
+impl private::Sealed for (PB6<Alternate<OpenDrain>>, PB7<Alternate<OpenDrain>>) {}
+impl private::Sealed for (PB8<Alternate<OpenDrain>>, PB9<Alternate<OpenDrain>>) {}
+impl private::Sealed for (PB10<Alternate<OpenDrain>>, PB11<Alternate<OpenDrain>>) {}
+
+impl Pins<I2C0> for (PB6<Alternate<OpenDrain>>, PB7<Alternate<OpenDrain>>) {
+    const REMAP: bool = false;
+}
+
+impl Pins<I2C0> for (PB8<Alternate<OpenDrain>>, PB9<Alternate<OpenDrain>>) {
+    const REMAP: bool = true;
+}
+
+impl Pins<I2C1> for (PB10<Alternate<OpenDrain>>, PB11<Alternate<OpenDrain>>) {
+    const REMAP: bool = false;
+}
+
+/// I2C master abstraction
+pub struct I2c<I2C, PINS> {
+    i2c: I2C,
+    pins: PINS,
+    mode: Mode,
+    clocks: Clocks,
+    timeout: u32,
+}
+
+// Default number of status register polls before giving up a wait
+const DEFAULT_TIMEOUT: u32 = 100_000;
+
+impl<PINS> I2c<I2C0, PINS> {
+    /// Power on and create I2C0 master instance
+    pub fn i2c0(
+        i2c0: I2C0,
+        pins: PINS,
+        pcf0: &mut PCF0,
+        mode: Mode,
+        clocks: Clocks,
+        apb1: &mut APB1,
+    ) -> Self
+    where
+        PINS: Pins<I2C0>,
+    {
+        riscv::interrupt::free(|_| {
+            // enable and reset i2c peripheral
+            apb1.en().modify(|_, w| w.i2c0en().set_bit());
+            apb1.rst().modify(|_, w| w.i2c0rst().set_bit());
+            apb1.rst().modify(|_, w| w.i2c0rst().clear_bit());
+            // set i2c remap
+            pcf0.pcf0().modify(|_, w| w.i2c0_remap().bit(PINS::REMAP));
+        });
+        let ans = I2c {
+            i2c: i2c0,
+            pins,
+            mode,
+            clocks,
+            timeout: DEFAULT_TIMEOUT,
+        };
+        ans.init();
+        ans
+    }
+
+    /// Power down and return ownership of owned registers
+    pub fn release(self, apb1: &mut APB1) -> (I2C0, PINS) {
+        self.i2c.ctl0.modify(|_, w| w.i2cen().clear_bit());
+        apb1.en().modify(|_, w| w.i2c0en().clear_bit());
+        (self.i2c, self.pins)
+    }
+}
+
+impl<PINS> I2c<I2C1, PINS> {
+    /// Power on and create I2C1 master instance
+    pub fn i2c1(i2c1: I2C1, pins: PINS, mode: Mode, clocks: Clocks, apb1: &mut APB1) -> Self
+    where
+        PINS: Pins<I2C1>,
+    {
+        riscv::interrupt::free(|_| {
+            // enable and reset i2c peripheral
+            apb1.en().modify(|_, w| w.i2c1en().set_bit());
+            apb1.rst().modify(|_, w| w.i2c1rst().set_bit());
+            apb1.rst().modify(|_, w| w.i2c1rst().clear_bit());
+        });
+        let ans = I2c {
+            i2c: i2c1,
+            pins,
+            mode,
+            clocks,
+            timeout: DEFAULT_TIMEOUT,
+        };
+        ans.init();
+        ans
+    }
+
+    /// Power down and return ownership of owned registers
+    pub fn release(self, apb1: &mut APB1) -> (I2C1, PINS) {
+        self.i2c.ctl0.modify(|_, w| w.i2cen().clear_bit());
+        apb1.en().modify(|_, w| w.i2c1en().clear_bit());
+        (self.i2c, self.pins)
+    }
+}
+
+impl<I2C, PINS> I2c<I2C, PINS>
+where
+    I2C: Deref<Target = RegisterBlock>,
+{
+    /// Sets the maximum number of status register polls when waiting for a
+    /// bus event, after which `Error::Timeout` is returned.
+    pub fn set_timeout(&mut self, polls: u32) {
+        self.timeout = polls;
+    }
+
+    // configure timing registers and enable the peripheral
+    fn init(&self) {
+        let timing = calc_timing(self.clocks.ck_apb1(), self.mode);
+        // timing registers can only be configured when I2C is disabled
+        self.i2c.ctl0.write(|w| w.i2cen().clear_bit());
+        self.i2c
+            .ctl1
+            .write(|w| unsafe { w.i2cclk().bits(timing.i2cclk) });
+        self.i2c
+            .rt
+            .write(|w| unsafe { w.risetime().bits(timing.risetime) });
+        self.i2c.ckcfg.write(|w| unsafe {
+            w.fast()
+                .bit(timing.fast)
+                .dtcy()
+                .bit(timing.dtcy)
+                .clkc()
+                .bits(timing.clkc)
+        });
+        self.i2c.ctl0.write(|w| w.i2cen().set_bit());
+    }
+
+    // Check error flags; if any error occurred, clear the flag and return it.
+    //
+    // Error flags in STAT0 are cleared by writing 0; writing 1 has no effect,
+    // so the other flags are written as 1 to keep them.
+    fn check_errors(&self) -> Result<stat0::R, Error> {
+        let stat0 = self.i2c.stat0.read();
+        if stat0.berr().bit_is_set() {
+            self.i2c
+                .stat0
+                .write(|w| unsafe { w.bits(!0).berr().clear_bit() });
+            Err(Error::Bus)
+        } else if stat0.lostarb().bit_is_set() {
+            self.i2c
+                .stat0
+                .write(|w| unsafe { w.bits(!0).lostarb().clear_bit() });
+            Err(Error::ArbitrationLost)
+        } else if stat0.aerr().bit_is_set() {
+            self.i2c
+                .stat0
+                .write(|w| unsafe { w.bits(!0).aerr().clear_bit() });
+            Err(Error::Acknowledge)
+        } else if stat0.ouerr().bit_is_set() {
+            self.i2c
+                .stat0
+                .write(|w| unsafe { w.bits(!0).ouerr().clear_bit() });
+            Err(Error::Overrun)
+        } else if stat0.smbto().bit_is_set() {
+            self.i2c
+                .stat0
+                .write(|w| unsafe { w.bits(!0).smbto().clear_bit() });
+            Err(Error::SmbusTimeout)
+        } else if stat0.pecerr().bit_is_set() {
+            self.i2c
+                .stat0
+                .write(|w| unsafe { w.bits(!0).pecerr().clear_bit() });
+            Err(Error::Pec)
+        } else {
+            Ok(stat0)
+        }
+    }
+
+    // Busy wait until the flag in STAT0 is set, an error occurs or the wait
+    // times out
+    #[inline]
+    fn wait_for(&self, flag: impl Fn(&stat0::R) -> bool) -> Result<(), Error> {
+        for _ in 0..self.timeout {
+            let stat0 = self.check_errors()?;
+            if flag(&stat0) {
+                return Ok(());
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    #[inline]
+    fn wait_bus_idle(&self) -> Result<(), Error> {
+        for _ in 0..self.timeout {
+            if self.i2c.stat1.read().i2cbsy().bit_is_clear() {
+                return Ok(());
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    // Generate a START (or repeated START) condition and send the address
+    fn start(&self, address: u8, read: bool) -> Result<(), Error> {
+        // STOP bit is cleared by hardware when STOP condition is detected
+        let mut polls = 0;
+        while self.i2c.ctl0.read().stop().bit_is_set() {
+            polls += 1;
+            if polls >= self.timeout {
+                return Err(Error::Timeout);
+            }
+        }
+        self.i2c.ctl0.modify(|_, w| w.start().set_bit());
+        self.wait_for(|s| s.sbsend().bit_is_set())?;
+        // writing DATA after reading STAT0 clears SBSEND
+        self.i2c
+            .data
+            .write(|w| unsafe { w.trb().bits((address << 1) | read as u8) });
+        self.wait_for(|s| s.addsend().bit_is_set())
+    }
+
+    // ADDSEND is cleared by reading STAT0 and then STAT1
+    #[inline]
+    fn clear_addsend(&self) {
+        let _ = self.i2c.stat0.read();
+        let _ = self.i2c.stat1.read();
+    }
+
+    #[inline]
+    fn stop(&self) {
+        self.i2c.ctl0.modify(|_, w| w.stop().set_bit());
+    }
+
+    // Generate a STOP condition after an error if we are still bus master
+    #[inline]
+    fn stop_on_error<T>(&self, ans: Result<T, Error>) -> Result<T, Error> {
+        match ans {
+            Err(Error::ArbitrationLost) | Err(Error::Timeout) => {}
+            Err(_) => self.stop(),
+            Ok(_) => {}
+        }
+        ans
+    }
+
+    // Send address and bytes; the STOP condition is not generated
+    fn write_bytes(&self, address: u8, bytes: &[u8]) -> Result<(), Error> {
+        self.start(address, false)?;
+        self.clear_addsend();
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        for &byte in bytes {
+            self.wait_for(|s| s.tbe().bit_is_set())?;
+            self.i2c.data.write(|w| unsafe { w.trb().bits(byte) });
+        }
+        // wait until the last byte is transferred
+        self.wait_for(|s| s.btc().bit_is_set())
+    }
+
+    // Send address and read bytes into buffer; the STOP condition is generated
+    //
+    // Reception of the last bytes follows the sequence in Section 17.3.8 of the
+    // User Manual; one and two byte reception are special cases.
+    fn read_bytes(&self, address: u8, buffer: &mut [u8]) -> Result<(), Error> {
+        let i2c = &self.i2c;
+        match buffer.len() {
+            0 => {
+                // only address the slave device
+                self.start(address, true)?;
+                i2c.ctl0.modify(|_, w| w.acken().clear_bit());
+                self.clear_addsend();
+                self.stop();
+            }
+            1 => {
+                i2c.ctl0.modify(|_, w| w.acken().clear_bit());
+                self.start(address, true)?;
+                // the STOP must be set right after ADDSEND is cleared
+                riscv::interrupt::free(|_| {
+                    self.clear_addsend();
+                    self.stop();
+                });
+                self.wait_for(|s| s.rbne().bit_is_set())?;
+                buffer[0] = i2c.data.read().trb().bits();
+            }
+            2 => {
+                // NACK the next byte, i.e. the second one
+                i2c.ctl0.modify(|_, w| w.acken().set_bit().poap().set_bit());
+                self.start(address, true)?;
+                i2c.ctl0.modify(|_, w| w.acken().clear_bit());
+                self.clear_addsend();
+                // wait until both bytes are received
+                self.wait_for(|s| s.btc().bit_is_set())?;
+                self.stop();
+                buffer[0] = i2c.data.read().trb().bits();
+                buffer[1] = i2c.data.read().trb().bits();
+                i2c.ctl0.modify(|_, w| w.poap().clear_bit());
+            }
+            _ => {
+                i2c.ctl0.modify(|_, w| w.acken().set_bit());
+                self.start(address, true)?;
+                self.clear_addsend();
+                self.receive_acked(buffer)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Receive 3 or more bytes with ACKEN set, NACK the last byte and generate
+    // the STOP condition
+    fn receive_acked(&self, buffer: &mut [u8]) -> Result<(), Error> {
+        let i2c = &self.i2c;
+        let (head, tail) = buffer.split_at_mut(buffer.len() - 3);
+        for byte in head {
+            self.wait_for(|s| s.rbne().bit_is_set())?;
+            *byte = i2c.data.read().trb().bits();
+        }
+        // byte N-2 in DATA, byte N-1 in shift register
+        self.wait_for(|s| s.btc().bit_is_set())?;
+        i2c.ctl0.modify(|_, w| w.acken().clear_bit());
+        tail[0] = i2c.data.read().trb().bits();
+        // byte N-1 in DATA, byte N in shift register
+        self.wait_for(|s| s.btc().bit_is_set())?;
+        self.stop();
+        tail[1] = i2c.data.read().trb().bits();
+        self.wait_for(|s| s.rbne().bit_is_set())?;
+        tail[2] = i2c.data.read().trb().bits();
+        Ok(())
+    }
+}
+
+impl<I2C, PINS> Write for I2c<I2C, PINS>
+where
+    I2C: Deref<Target = RegisterBlock>,
+{
+    type Error = Error;
+
+    fn try_write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.wait_bus_idle()?;
+        let ans = self.write_bytes(address, bytes);
+        let ans = self.stop_on_error(ans);
+        if ans.is_ok() {
+            self.stop();
+        }
+        ans
+    }
+}
+
+impl<I2C, PINS> Read for I2c<I2C, PINS>
+where
+    I2C: Deref<Target = RegisterBlock>,
+{
+    type Error = Error;
+
+    fn try_read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.wait_bus_idle()?;
+        let ans = self.read_bytes(address, buffer);
+        self.stop_on_error(ans)
+    }
+}
+
+impl<I2C, PINS> WriteRead for I2c<I2C, PINS>
+where
+    I2C: Deref<Target = RegisterBlock>,
+{
+    type Error = Error;
+
+    fn try_write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        self.wait_bus_idle()?;
+        // no STOP between write and read, use a repeated START instead
+        let ans = self
+            .write_bytes(address, bytes)
+            .and_then(|_| self.read_bytes(address, buffer));
+        self.stop_on_error(ans)
+    }
+}
+
+// Busy wait for about `cycles` core clock cycles
+#[inline]
+fn spin(cycles: u32) {
+    for _ in 0..cycles {
+        hint::spin_loop();
+    }
+}
+
+macro_rules! i2c_recover {
+    ($(
+        $I2CX:ident: ($SCL:ident, $SDA:ident, $CTL:ident),
+    )+) => {
+$(
+impl I2c<$I2CX, ($SCL<Alternate<OpenDrain>>, $SDA<Alternate<OpenDrain>>)> {
+    /// Frees a bus held by a stuck slave device.
+    ///
+    /// A slave may hold SDA low forever if it is reset or browns out in the
+    /// middle of a transfer. This function takes over both pins as open drain
+    /// outputs, clocks out up to 9 pulses on SCL until SDA is released, then
+    /// generates a STOP condition. The pins are returned to alternate open
+    /// drain mode and the I2C peripheral is reset and configured again.
+    pub fn recover(self, ctl: &mut $CTL) -> Self {
+        let I2c { i2c, pins: (scl, sda), mode, clocks, timeout } = self;
+        // hand over pins to GPIO
+        i2c.ctl0.modify(|_, w| w.i2cen().clear_bit());
+        let mut scl = scl.into_open_drain_output(ctl);
+        let mut sda = sda.into_open_drain_output(ctl);
+        // half of SCL period in core clock cycles; the spin loop takes at
+        // least one cycle each iteration so the bus is never overclocked
+        let half_period = clocks.ck_sys().0 / mode.frequency().0 / 2;
+        // wait for SCL to go high; the slave may stretch the clock
+        let release_scl = |scl: &mut $SCL<Output<OpenDrain>>| {
+            let _ = scl.try_set_high();
+            for _ in 0..timeout {
+                if scl.try_is_high().unwrap_or(true) {
+                    break;
+                }
+            }
+            spin(half_period);
+        };
+        let _ = sda.try_set_high();
+        release_scl(&mut scl);
+        for _ in 0..9 {
+            if sda.try_is_high().unwrap_or(true) {
+                break;
+            }
+            let _ = scl.try_set_low();
+            spin(half_period);
+            release_scl(&mut scl);
+        }
+        // STOP condition: SDA rises while SCL is high
+        let _ = scl.try_set_low();
+        spin(half_period);
+        let _ = sda.try_set_low();
+        spin(half_period);
+        release_scl(&mut scl);
+        let _ = sda.try_set_high();
+        spin(half_period);
+        // hand pins back to I2C peripheral
+        let scl = scl.into_alternate_open_drain(ctl);
+        let sda = sda.into_alternate_open_drain(ctl);
+        // software reset clears the busy flag and internal states
+        i2c.ctl0.write(|w| w.sreset().set_bit());
+        i2c.ctl0.write(|w| w.sreset().clear_bit());
+        let ans = I2c { i2c, pins: (scl, sda), mode, clocks, timeout };
+        ans.init();
+        ans
+    }
+}
+)+
+    };
+}
+
+i2c_recover! {
+    I2C0: (PB6, PB7, CTL0),
+    I2C0: (PB8, PB9, CTL1),
+    I2C1: (PB10, PB11, CTL1),
+}
+
+/// Own address of an I2C slave
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Address {
+    /// 7-bit address
+    SevenBit(u8),
+    /// 10-bit address
+    TenBit(u16),
+}
+
+/// I2C slave config
+pub struct SlaveConfig {
+    /// Primary own address
+    pub address: Address,
+    /// Second 7-bit own address in dual address mode
+    pub dual_address: Option<u8>,
+    /// Respond to the general call address 0x00
+    pub general_call: bool,
+    /// Hold SCL low when the slave is not ready to send or receive data
+    pub clock_stretching: bool,
+}
+
+impl SlaveConfig {
+    /// Slave config with given own address; general call is disabled and
+    /// clock stretching is enabled.
+    pub fn new(address: Address) -> Self {
+        SlaveConfig {
+            address,
+            dual_address: None,
+            general_call: false,
+            clock_stretching: true,
+        }
+    }
+
+    pub fn dual_address(mut self, address: u8) -> Self {
+        self.dual_address = Some(address);
+        self
+    }
+
+    pub fn general_call(mut self, enable: bool) -> Self {
+        self.general_call = enable;
+        self
+    }
+
+    pub fn clock_stretching(mut self, enable: bool) -> Self {
+        self.clock_stretching = enable;
+        self
+    }
+}
+
+/// Transfer direction requested by the master
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    /// Master reads from this slave; the slave transmits
+    Read,
+    /// Master writes to this slave; the slave receives
+    Write,
+}
+
+/// I2C slave event
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Event {
+    /// Own address (or general call address) is matched
+    AddressMatched {
+        /// Transfer direction requested by the master
+        direction: Direction,
+    },
+    /// A byte is received from the master
+    ByteReceived(u8),
+    /// The master requests a byte; answer with `I2cSlave::write`
+    ByteRequested,
+    /// The transfer has ended by a STOP condition, or by the master not
+    /// acknowledging the last byte it reads
+    Stop,
+}
+
+/// I2C slave abstraction
+///
+/// The slave is driven by events. Call `poll` in a loop, or `listen` and call
+/// `poll` from the event and error interrupt handlers (`I2C0_EV_IRQHandler`
+/// and `I2C0_ER_IRQHandler` for I2C0).
+pub struct I2cSlave<I2C, PINS> {
+    i2c: I2C,
+    pins: PINS,
+}
+
+impl<PINS> I2cSlave<I2C0, PINS> {
+    /// Power on and create I2C0 slave instance
+    pub fn i2c0(
+        i2c0: I2C0,
+        pins: PINS,
+        pcf0: &mut PCF0,
+        config: SlaveConfig,
+        clocks: Clocks,
+        apb1: &mut APB1,
+    ) -> Self
+    where
+        PINS: Pins<I2C0>,
+    {
+        riscv::interrupt::free(|_| {
+            // enable and reset i2c peripheral
+            apb1.en().modify(|_, w| w.i2c0en().set_bit());
+            apb1.rst().modify(|_, w| w.i2c0rst().set_bit());
+            apb1.rst().modify(|_, w| w.i2c0rst().clear_bit());
+            // set i2c remap
+            pcf0.pcf0().modify(|_, w| w.i2c0_remap().bit(PINS::REMAP));
+        });
+        let ans = I2cSlave { i2c: i2c0, pins };
+        ans.init(&config, clocks);
+        ans
+    }
+
+    /// Power down and return ownership of owned registers
+    pub fn release(self, apb1: &mut APB1) -> (I2C0, PINS) {
+        self.i2c.ctl0.modify(|_, w| w.i2cen().clear_bit());
+        apb1.en().modify(|_, w| w.i2c0en().clear_bit());
+        (self.i2c, self.pins)
+    }
+}
+
+impl<PINS> I2cSlave<I2C1, PINS> {
+    /// Power on and create I2C1 slave instance
+    pub fn i2c1(
+        i2c1: I2C1,
+        pins: PINS,
+        config: SlaveConfig,
+        clocks: Clocks,
+        apb1: &mut APB1,
+    ) -> Self
+    where
+        PINS: Pins<I2C1>,
+    {
+        riscv::interrupt::free(|_| {
+            // enable and reset i2c peripheral
+            apb1.en().modify(|_, w| w.i2c1en().set_bit());
+            apb1.rst().modify(|_, w| w.i2c1rst().set_bit());
+            apb1.rst().modify(|_, w| w.i2c1rst().clear_bit());
+        });
+        let ans = I2cSlave { i2c: i2c1, pins };
+        ans.init(&config, clocks);
+        ans
+    }
+
+    /// Power down and return ownership of owned registers
+    pub fn release(self, apb1: &mut APB1) -> (I2C1, PINS) {
+        self.i2c.ctl0.modify(|_, w| w.i2cen().clear_bit());
+        apb1.en().modify(|_, w| w.i2c1en().clear_bit());
+        (self.i2c, self.pins)
+    }
+}
+
+impl<I2C, PINS> I2cSlave<I2C, PINS>
+where
+    I2C: Deref<Target = RegisterBlock>,
+{
+    fn init(&self, config: &SlaveConfig, clocks: Clocks) {
+        let i2cclk = clocks.ck_apb1().0 / 1_000_000;
+        assert!((2..=54).contains(&i2cclk), "invalid APB1 frequency for I2C");
+        self.i2c.ctl0.write(|w| w.i2cen().clear_bit());
+        self.i2c
+            .ctl1
+            .write(|w| unsafe { w.i2cclk().bits(i2cclk as u8) });
+        match config.address {
+            Address::SevenBit(address) => self
+                .i2c
+                .saddr0
+                .write(|w| unsafe { w.addformat().clear_bit().address7_1().bits(address & 0x7F) }),
+            Address::TenBit(address) => self.i2c.saddr0.write(|w| unsafe {
+                w.addformat()
+                    .set_bit()
+                    .address9_8()
+                    .bits(((address >> 8) & 0b11) as u8)
+                    .address7_1()
+                    .bits(((address >> 1) & 0x7F) as u8)
+                    .address0()
+                    .bit(address & 1 != 0)
+            }),
+        }
+        match config.dual_address {
+            Some(address) => self
+                .i2c
+                .saddr1
+                .write(|w| unsafe { w.address2().bits(address & 0x7F).duaden().set_bit() }),
+            None => self.i2c.saddr1.write(|w| w.duaden().clear_bit()),
+        }
+        self.i2c.ctl0.write(|w| {
+            w.gcen()
+                .bit(config.general_call)
+                // note: SS bit set means clock stretching is disabled
+                .ss()
+                .bit(!config.clock_stretching)
+                .i2cen()
+                .set_bit()
+        });
+        // ACKEN could only be set after I2C is enabled
+        self.i2c.ctl0.modify(|_, w| w.acken().set_bit());
+    }
+
+    /// Enables the event, buffer and error interrupts.
+    pub fn listen(&mut self) {
+        self.i2c
+            .ctl1
+            .modify(|_, w| w.evie().set_bit().bufie().set_bit().errie().set_bit());
+    }
+
+    /// Disables the event, buffer and error interrupts.
+    pub fn unlisten(&mut self) {
+        self.i2c
+            .ctl1
+            .modify(|_, w| w.evie().clear_bit().bufie().clear_bit().errie().clear_bit());
+    }
+
+    /// Checks if the last matched address is the general call address.
+    pub fn is_general_call(&self) -> bool {
+        self.i2c.stat1.read().rxgc().bit_is_set()
+    }
+
+    /// Handles the pending bus event and returns it.
+    ///
+    /// Returns `WouldBlock` if there is no event pending. Errors are returned
+    /// as `Other`; the error flag is cleared before returning.
+    pub fn poll(&mut self) -> nb::Result<Event, Error> {
+        let i2c = &self.i2c;
+        let stat0 = i2c.stat0.read();
+        if stat0.berr().bit_is_set() {
+            i2c.stat0
+                .write(|w| unsafe { w.bits(!0).berr().clear_bit() });
+            return Err(nb::Error::Other(Error::Bus));
+        }
+        if stat0.ouerr().bit_is_set() {
+            i2c.stat0
+                .write(|w| unsafe { w.bits(!0).ouerr().clear_bit() });
+            return Err(nb::Error::Other(Error::Overrun));
+        }
+        if stat0.aerr().bit_is_set() {
+            // the master does not acknowledge the last byte it reads
+            i2c.stat0
+                .write(|w| unsafe { w.bits(!0).aerr().clear_bit() });
+            return Ok(Event::Stop);
+        }
+        if stat0.addsend().bit_is_set() {
+            // reading STAT1 after STAT0 clears ADDSEND
+            let direction = if i2c.stat1.read().tr().bit_is_set() {
+                Direction::Read
+            } else {
+                Direction::Write
+            };
+            return Ok(Event::AddressMatched { direction });
+        }
+        if stat0.rbne().bit_is_set() {
+            return Ok(Event::ByteReceived(i2c.data.read().trb().bits()));
+        }
+        if stat0.stpdet().bit_is_set() {
+            // reading STAT0 and then writing CTL0 clears STPDET
+            i2c.ctl0.modify(|_, w| w);
+            return Ok(Event::Stop);
+        }
+        if stat0.tbe().bit_is_set() && i2c.stat1.read().tr().bit_is_set() {
+            return Ok(Event::ByteRequested);
+        }
+        Err(nb::Error::WouldBlock)
+    }
+
+    /// Sends a byte to the master, as the answer of `Event::ByteRequested`.
+    pub fn write(&mut self, byte: u8) {
+        self.i2c.data.write(|w| unsafe { w.trb().bits(byte) });
+    }
+}
+
+impl<I2C, PINS> I2cSlave<I2C, PINS>
+where
+    I2C: Deref<Target = RegisterBlock>,
+{
+    /// Switches to SMBus device mode; if `arp` is true, the SMBus device
+    /// default address 0b1100001 is also acknowledged for address resolution.
+    pub fn smbus_device(&mut self, arp: bool) {
+        self.i2c.ctl0.modify(|_, w| {
+            w.smben()
+                .set_bit()
+                .smbsel()
+                .set_bit()
+                .arpen()
+                .bit(arp)
+                .pecen()
+                .set_bit()
+        });
+    }
+
+    /// Drives the SMBA pin low to signal an SMBus alert to the host if
+    /// `alert` is true, otherwise releases the pin.
+    pub fn set_alert(&mut self, alert: bool) {
+        self.i2c.ctl0.modify(|_, w| w.salt().bit(alert));
+    }
+
+    /// Checks if the last matched address is the SMBus device default address.
+    pub fn is_default_address(&self) -> bool {
+        self.i2c.stat1.read().defsmb().bit_is_set()
+    }
+}
+
+/// Computes SMBus packet error code of `data`, continuing from `crc`.
+///
+/// The PEC is a CRC-8 with polynomial x^8 + x^2 + x + 1 over every byte of
+/// the message, including address bytes; start from `crc = 0` for a new
+/// message. A message followed by its own PEC byte has a PEC of zero.
+pub fn pec(crc: u8, data: &[u8]) -> u8 {
+    data.iter().fold(crc, |crc, &byte| {
+        let mut crc = crc ^ byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+/// Packet error checking mode
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Pec {
+    /// No packet error checking
+    Disabled,
+    /// PEC is computed by the I2C peripheral
+    Hardware,
+    /// PEC is computed by software with the `pec` function
+    Software,
+}
+
+// Maximum data length of SMBus block transfers
+const BLOCK_MAX: usize = 32;
+
+// SMBus alert response address
+const ALERT_RESPONSE_ADDRESS: u8 = 0b000_1100;
+
+/// SMBus host
+///
+/// The SMBA pin (PB5 for I2C0, PB12 for I2C1) should be configured into
+/// `Alternate<OpenDrain>` mode for the alert function.
+pub struct Smbus<I2C, PINS> {
+    i2c: I2c<I2C, PINS>,
+    pec: Pec,
+}
+
+impl<I2C, PINS> Smbus<I2C, PINS>
+where
+    I2C: Deref<Target = RegisterBlock>,
+{
+    /// Switches the I2C master into SMBus host mode
+    pub fn new(i2c: I2c<I2C, PINS>, pec: Pec) -> Self {
+        i2c.i2c.ctl0.modify(|_, w| {
+            w.smben()
+                .set_bit()
+                .smbsel()
+                .clear_bit()
+                .pecen()
+                .bit(pec == Pec::Hardware)
+        });
+        Smbus { i2c, pec }
+    }
+
+    /// Switches back to I2C mode and returns the I2C master
+    pub fn free(self) -> I2c<I2C, PINS> {
+        self.i2c.i2c.ctl0.modify(|_, w| {
+            w.smben()
+                .clear_bit()
+                .pecen()
+                .clear_bit()
+                .arpen()
+                .clear_bit()
+        });
+        self.i2c
+    }
+
+    /// Enables or disables address resolution protocol (ARP).
+    pub fn set_arp(&mut self, enable: bool) {
+        self.i2c.i2c.ctl0.modify(|_, w| w.arpen().bit(enable));
+    }
+
+    /// Checks if an SMBus alert from a device is detected on the SMBA pin.
+    pub fn is_alert(&self) -> bool {
+        self.i2c.i2c.stat0.read().smbalt().bit_is_set()
+    }
+
+    /// Clears the SMBus alert flag.
+    pub fn clear_alert(&mut self) {
+        self.i2c
+            .i2c
+            .stat0
+            .write(|w| unsafe { w.bits(!0).smbalt().clear_bit() });
+    }
+
+    /// Reads the alert response address and returns the 7-bit address of the
+    /// device which signals the alert.
+    pub fn alert_response(&mut self) -> Result<u8, Error> {
+        let mut buffer = [0u8; 1];
+        self.i2c.try_read(ALERT_RESPONSE_ADDRESS, &mut buffer)?;
+        Ok(buffer[0] >> 1)
+    }
+
+    /// Quick command, sends the read/write bit as the only data.
+    pub fn quick_command(&mut self, address: u8, read: bool) -> Result<(), Error> {
+        if read {
+            self.i2c.try_read(address, &mut [])
+        } else {
+            self.i2c.try_write(address, &[])
+        }
+    }
+
+    /// Send byte protocol
+    pub fn send_byte(&mut self, address: u8, byte: u8) -> Result<(), Error> {
+        self.write(address, &[byte])
+    }
+
+    /// Receive byte protocol
+    pub fn receive_byte(&mut self, address: u8) -> Result<u8, Error> {
+        let mut buffer = [0u8; 1];
+        self.read(address, &[], &mut buffer)?;
+        Ok(buffer[0])
+    }
+
+    /// Write byte protocol
+    pub fn write_byte(&mut self, address: u8, command: u8, byte: u8) -> Result<(), Error> {
+        self.write(address, &[command, byte])
+    }
+
+    /// Read byte protocol
+    pub fn read_byte(&mut self, address: u8, command: u8) -> Result<u8, Error> {
+        let mut buffer = [0u8; 1];
+        self.read(address, &[command], &mut buffer)?;
+        Ok(buffer[0])
+    }
+
+    /// Write word protocol; the low byte is sent first
+    pub fn write_word(&mut self, address: u8, command: u8, word: u16) -> Result<(), Error> {
+        let [lo, hi] = word.to_le_bytes();
+        self.write(address, &[command, lo, hi])
+    }
+
+    /// Read word protocol; the low byte is received first
+    pub fn read_word(&mut self, address: u8, command: u8) -> Result<u16, Error> {
+        let mut buffer = [0u8; 2];
+        self.read(address, &[command], &mut buffer)?;
+        Ok(u16::from_le_bytes(buffer))
+    }
+
+    /// Process call protocol, writes a word and reads a word in one transfer
+    pub fn process_call(&mut self, address: u8, command: u8, word: u16) -> Result<u16, Error> {
+        let [lo, hi] = word.to_le_bytes();
+        let mut buffer = [0u8; 2];
+        self.read(address, &[command, lo, hi], &mut buffer)?;
+        Ok(u16::from_le_bytes(buffer))
+    }
+
+    /// Block write protocol; at most 32 bytes could be written
+    pub fn block_write(&mut self, address: u8, command: u8, bytes: &[u8]) -> Result<(), Error> {
+        assert!(bytes.len() <= BLOCK_MAX, "SMBus block too long");
+        let mut buffer = [0u8; BLOCK_MAX + 2];
+        buffer[0] = command;
+        buffer[1] = bytes.len() as u8;
+        buffer[2..bytes.len() + 2].copy_from_slice(bytes);
+        self.write(address, &buffer[..bytes.len() + 2])
+    }
+
+    /// Block read protocol; returns the number of bytes read into `buffer`
+    pub fn block_read(
+        &mut self,
+        address: u8,
+        command: u8,
+        buffer: &mut [u8; BLOCK_MAX],
+    ) -> Result<usize, Error> {
+        self.i2c.wait_bus_idle()?;
+        self.reset_pec();
+        let mut block = [0u8; BLOCK_MAX + 2];
+        let ans = self
+            .i2c
+            .write_bytes(address, &[command])
+            .and_then(|_| self.receive_block(address, &mut block));
+        let count = self.i2c.stop_on_error(ans)?;
+        let data_len = count + 1;
+        match self.pec {
+            Pec::Disabled => {}
+            Pec::Hardware => self.check_hardware_pec()?,
+            Pec::Software => {
+                let crc = pec(0, &[address << 1, command, (address << 1) | 1]);
+                if pec(crc, &block[..data_len]) != block[data_len] {
+                    return Err(Error::Pec);
+                }
+            }
+        }
+        buffer[..count].copy_from_slice(&block[1..data_len]);
+        Ok(count)
+    }
+
+    // PEC byte appended after the message, if any
+    #[inline]
+    fn pec_len(&self) -> usize {
+        match self.pec {
+            Pec::Disabled => 0,
+            _ => 1,
+        }
+    }
+
+    // the hardware PEC value is reset by disabling PEC calculation
+    fn reset_pec(&self) {
+        if self.pec == Pec::Hardware {
+            let i2c = &self.i2c.i2c;
+            i2c.ctl0.modify(|_, w| w.pecen().clear_bit());
+            i2c.ctl0.modify(|_, w| w.pecen().set_bit());
+        }
+    }
+
+    // After the PEC byte is received, the PEC over the whole message should
+    // be zero.
+    fn check_hardware_pec(&self) -> Result<(), Error> {
+        if self.i2c.i2c.stat1.read().pecv().bits() == 0 {
+            Ok(())
+        } else {
+            Err(Error::Pec)
+        }
+    }
+
+    // Write `bytes` followed by the PEC byte if enabled
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Error> {
+        self.i2c.wait_bus_idle()?;
+        self.reset_pec();
+        let ans = match self.pec {
+            Pec::Disabled => self.i2c.write_bytes(address, bytes),
+            Pec::Hardware => self.write_hardware_pec(address, bytes),
+            Pec::Software => {
+                let mut buffer = [0u8; BLOCK_MAX + 3];
+                buffer[..bytes.len()].copy_from_slice(bytes);
+                buffer[bytes.len()] = pec(pec(0, &[address << 1]), bytes);
+                self.i2c.write_bytes(address, &buffer[..bytes.len() + 1])
+            }
+        };
+        let ans = self.i2c.stop_on_error(ans);
+        if ans.is_ok() {
+            self.i2c.stop();
+        }
+        ans
+    }
+
+    fn write_hardware_pec(&self, address: u8, bytes: &[u8]) -> Result<(), Error> {
+        let i2c = &self.i2c;
+        i2c.start(address, false)?;
+        i2c.clear_addsend();
+        for &byte in bytes {
+            i2c.wait_for(|s| s.tbe().bit_is_set())?;
+            i2c.i2c.data.write(|w| unsafe { w.trb().bits(byte) });
+        }
+        // the PEC byte is sent after the last data byte
+        i2c.wait_for(|s| s.tbe().bit_is_set())?;
+        i2c.i2c.ctl0.modify(|_, w| w.pectrans().set_bit());
+        i2c.wait_for(|s| s.btc().bit_is_set())
+    }
+
+    // Write `bytes` if any, then read into `buffer` with a repeated START and
+    // check the PEC byte if enabled
+    fn read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+        self.i2c.wait_bus_idle()?;
+        self.reset_pec();
+        let len = buffer.len();
+        let mut message = [0u8; 3];
+        let message = &mut message[..len + self.pec_len()];
+        let ans = if bytes.is_empty() {
+            self.i2c.read_bytes(address, message)
+        } else {
+            self.i2c
+                .write_bytes(address, bytes)
+                .and_then(|_| self.i2c.read_bytes(address, message))
+        };
+        self.i2c.stop_on_error(ans)?;
+        match self.pec {
+            Pec::Disabled => {}
+            Pec::Hardware => self.check_hardware_pec()?,
+            Pec::Software => {
+                let mut crc = 0;
+                if !bytes.is_empty() {
+                    crc = pec(pec(crc, &[address << 1]), bytes);
+                }
+                if pec(pec(crc, &[(address << 1) | 1]), message) != 0 {
+                    return Err(Error::Pec);
+                }
+            }
+        }
+        buffer.copy_from_slice(&message[..len]);
+        Ok(())
+    }
+
+    // Receive byte count, data and PEC byte of a block; returns the count
+    //
+    // Whether to NACK is decided right after the byte count is read, so the
+    // count read and the acknowledge configuration should not be interrupted.
+    fn receive_block(&self, address: u8, block: &mut [u8; BLOCK_MAX + 2]) -> Result<usize, Error> {
+        let i2c = &self.i2c;
+        let pec_len = self.pec_len();
+        i2c.i2c
+            .ctl0
+            .modify(|_, w| w.acken().set_bit().poap().clear_bit());
+        i2c.start(address, true)?;
+        i2c.clear_addsend();
+        i2c.wait_for(|s| s.rbne().bit_is_set())?;
+        let count = riscv::interrupt::free(|_| {
+            let count = i2c.i2c.data.read().trb().bits() as usize;
+            match count + pec_len {
+                // NACK the byte being received
+                0 | 1 => {
+                    i2c.i2c.ctl0.modify(|_, w| w.acken().clear_bit());
+                    i2c.stop();
+                }
+                // NACK the byte after the one being received
+                2 => i2c
+                    .i2c
+                    .ctl0
+                    .modify(|_, w| w.poap().set_bit().acken().clear_bit()),
+                // the slave sends more than a block could hold
+                n if n > BLOCK_MAX + pec_len => {
+                    i2c.i2c.ctl0.modify(|_, w| w.acken().clear_bit());
+                    i2c.stop();
+                }
+                _ => {}
+            }
+            count
+        });
+        block[0] = count as u8;
+        match count + pec_len {
+            0 | 1 => {
+                i2c.wait_for(|s| s.rbne().bit_is_set())?;
+                block[1] = i2c.i2c.data.read().trb().bits();
+            }
+            2 => {
+                i2c.wait_for(|s| s.btc().bit_is_set())?;
+                i2c.stop();
+                block[1] = i2c.i2c.data.read().trb().bits();
+                block[2] = i2c.i2c.data.read().trb().bits();
+                i2c.i2c.ctl0.modify(|_, w| w.poap().clear_bit());
+            }
+            n if n > BLOCK_MAX + pec_len => {
+                i2c.wait_for(|s| s.rbne().bit_is_set())?;
+                let _ = i2c.i2c.data.read();
+                return Err(Error::Overrun);
+            }
+            n => i2c.receive_acked(&mut block[1..n + 1])?,
+        }
+        Ok(count)
+    }
+}
+
+/// I2C master with a DMA channel for transmission
+pub struct I2cTxDma<I2C, PINS, CH> {
+    i2c: I2c<I2C, PINS>,
+    channel: CH,
+    error: Option<Error>,
+}
+
+/// I2C master with a DMA channel for reception
+pub struct I2cRxDma<I2C, PINS, CH> {
+    i2c: I2c<I2C, PINS>,
+    channel: CH,
+    error: Option<Error>,
+}
+
+impl<I2C, PINS> I2c<I2C, PINS>
+where
+    I2C: Deref<Target = RegisterBlock>,
+{
+    #[inline]
+    fn data_address(&self) -> u32 {
+        &self.i2c.data as *const _ as u32
+    }
+
+    // Checks if any error flag is set without clearing it
+    #[inline]
+    fn is_error_pending(&self) -> bool {
+        let stat0 = self.i2c.stat0.read();
+        stat0.berr().bit_is_set()
+            || stat0.lostarb().bit_is_set()
+            || stat0.aerr().bit_is_set()
+            || stat0.ouerr().bit_is_set()
+            || stat0.smbto().bit_is_set()
+            || stat0.pecerr().bit_is_set()
+    }
+
+    // Stop DMA requests, record the error if any, and generate the STOP
+    // condition if this is still the bus master
+    fn finish_dma(&self, error: &mut Option<Error>) {
+        self.i2c
+            .ctl1
+            .modify(|_, w| w.dmaon().clear_bit().dmalst().clear_bit());
+        if let Err(e) = self.check_errors() {
+            if error.is_none() {
+                *error = Some(e);
+            }
+        }
+        if self.i2c.stat1.read().master().bit_is_set() {
+            self.stop();
+        }
+    }
+}
+
+impl<I2C, PINS, CH> I2cTxDma<I2C, PINS, CH>
+where
+    I2C: Deref<Target = RegisterBlock>,
+    CH: Channel,
+{
+    /// Writes all bytes in the buffer to the slave using DMA.
+    ///
+    /// The START condition and the address are sent before this function
+    /// returns; the STOP condition is generated when the transfer is waited
+    /// for. Errors could be read by `take_error` afterwards.
+    pub fn write_dma(
+        mut self,
+        address: u8,
+        buffer: &'static [u8],
+    ) -> Transfer<&'static [u8], Self> {
+        assert!(!buffer.is_empty(), "empty buffer for I2C DMA");
+        let data_address = self.i2c.data_address();
+        setup_channel(
+            &mut self.channel,
+            data_address,
+            buffer.as_ptr() as u32,
+            buffer.len(),
+            DmaDirection::MemoryToPeripheral,
+        );
+        self.error = self.begin(address).err();
+        Transfer::new(buffer, self)
+    }
+
+    fn begin(&mut self, address: u8) -> Result<(), Error> {
+        self.i2c.wait_bus_idle()?;
+        self.channel.start();
+        self.i2c.i2c.ctl1.modify(|_, w| w.dmaon().set_bit());
+        self.i2c.start(address, false)?;
+        // TBE is set after ADDSEND is cleared, which requests the first byte
+        self.i2c.clear_addsend();
+        Ok(())
+    }
+
+    /// Returns and clears the error of the last transfer.
+    pub fn take_error(&mut self) -> Option<Error> {
+        self.error.take()
+    }
+
+    /// Releases the I2C master and the DMA channel
+    pub fn release(self) -> (I2c<I2C, PINS>, CH) {
+        (self.i2c, self.channel)
+    }
+}
+
+impl<I2C, PINS, CH> TransferPayload for I2cTxDma<I2C, PINS, CH>
+where
+    I2C: Deref<Target = RegisterBlock>,
+    CH: Channel,
+{
+    fn is_done(&self) -> bool {
+        // the last byte is sent when BTC is set
+        self.error.is_some()
+            || self.i2c.is_error_pending()
+            || (self.channel.is_complete() && self.i2c.i2c.stat0.read().btc().bit_is_set())
+    }
+
+    fn stop(&mut self) {
+        self.channel.stop();
+        self.channel.clear_flags();
+        self.i2c.finish_dma(&mut self.error);
+    }
+}
+
+impl<I2C, PINS, CH> I2cRxDma<I2C, PINS, CH>
+where
+    I2C: Deref<Target = RegisterBlock>,
+    CH: Channel,
+{
+    /// Reads bytes from the slave to fill the buffer using DMA.
+    ///
+    /// The last byte is not acknowledged (DMALST); the STOP condition is
+    /// generated when the transfer is waited for. Errors could be read by
+    /// `take_error` afterwards.
+    pub fn read_dma(
+        mut self,
+        address: u8,
+        buffer: &'static mut [u8],
+    ) -> Transfer<&'static mut [u8], Self> {
+        assert!(!buffer.is_empty(), "empty buffer for I2C DMA");
+        let data_address = self.i2c.data_address();
+        let len = buffer.len();
+        setup_channel(
+            &mut self.channel,
+            data_address,
+            buffer.as_mut_ptr() as u32,
+            len,
+            DmaDirection::PeripheralToMemory,
+        );
+        self.error = self.begin(address, len).err();
+        Transfer::new(buffer, self)
+    }
+
+    fn begin(&mut self, address: u8, len: usize) -> Result<(), Error> {
+        self.i2c.wait_bus_idle()?;
+        self.channel.start();
+        let i2c = &self.i2c;
+        if len == 1 {
+            i2c.i2c.ctl0.modify(|_, w| w.acken().clear_bit());
+            i2c.i2c.ctl1.modify(|_, w| w.dmaon().set_bit());
+            i2c.start(address, true)?;
+            // the STOP must be set right after ADDSEND is cleared
+            riscv::interrupt::free(|_| {
+                i2c.clear_addsend();
+                i2c.stop();
+            });
+        } else {
+            // NACK is generated for the byte of the last DMA request
+            i2c.i2c.ctl0.modify(|_, w| w.acken().set_bit());
+            i2c.i2c
+                .ctl1
+                .modify(|_, w| w.dmaon().set_bit().dmalst().set_bit());
+            i2c.start(address, true)?;
+            i2c.clear_addsend();
+        }
+        Ok(())
+    }
+
+    /// Returns and clears the error of the last transfer.
+    pub fn take_error(&mut self) -> Option<Error> {
+        self.error.take()
+    }
+
+    /// Releases the I2C master and the DMA channel
+    pub fn release(self) -> (I2c<I2C, PINS>, CH) {
+        (self.i2c, self.channel)
+    }
+}
+
+impl<I2C, PINS, CH> TransferPayload for I2cRxDma<I2C, PINS, CH>
+where
+    I2C: Deref<Target = RegisterBlock>,
+    CH: Channel,
+{
+    fn is_done(&self) -> bool {
+        self.error.is_some() || self.i2c.is_error_pending() || self.channel.is_complete()
+    }
+
+    fn stop(&mut self) {
+        self.channel.stop();
+        self.channel.clear_flags();
+        self.i2c.finish_dma(&mut self.error);
+    }
+}
+
+macro_rules! i2c_dma {
+    ($($I2CX:ident: ($TXCH:ty, $RXCH:ty),)+) => {
+        $(
+            impl<PINS> I2c<$I2CX, PINS> {
+                /// Uses a DMA channel for transmission
+                pub fn with_tx_dma(self, channel: $TXCH) -> I2cTxDma<$I2CX, PINS, $TXCH> {
+                    I2cTxDma { i2c: self, channel, error: None }
+                }
+
+                /// Uses a DMA channel for reception
+                pub fn with_rx_dma(self, channel: $RXCH) -> I2cRxDma<$I2CX, PINS, $RXCH> {
+                    I2cRxDma { i2c: self, channel, error: None }
+                }
+            }
+        )+
+    };
+}
+
+i2c_dma! {
+    I2C0: (dma::dma0::C5, dma::dma0::C6),
+    I2C1: (dma::dma0::C3, dma::dma0::C4),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pec_check_value() {
+        // CRC-8/SMBUS catalogue check value
+        assert_eq!(pec(0, b"123456789"), 0xF4);
+        assert_eq!(pec(0, &[]), 0x00);
+        assert_eq!(pec(0, &[0x01]), 0x07);
+        assert_eq!(pec(0, &[0xFF]), 0xF3);
+    }
+
+    #[test]
+    fn pec_read_word() {
+        // MLX90614 datasheet: read word 0x3AD2 from RAM 0x07 at address 0x5A
+        let message = [0xB4, 0x07, 0xB5, 0xD2, 0x3A];
+        assert_eq!(pec(0, &message), 0x30);
+        // continued from a partial PEC
+        assert_eq!(pec(pec(0, &message[..2]), &message[2..]), 0x30);
+        // a message followed by its PEC has a PEC of zero
+        assert_eq!(pec(0, &[0xB4, 0x07, 0xB5, 0xD2, 0x3A, 0x30]), 0x00);
+    }
+
+    fn timing(i2cclk: u8, risetime: u8, fast: bool, dtcy: bool, clkc: u16) -> Timing {
+        Timing {
+            i2cclk,
+            risetime,
+            fast,
+            dtcy,
+            clkc,
+        }
+    }
+
+    #[test]
+    fn standard_mode_timing() {
+        // APB1 of a 108 MHz and a 72 MHz system clock
+        let mode = Mode::standard(Hertz(100_000));
+        assert_eq!(
+            calc_timing(Hertz(54_000_000), mode),
+            timing(54, 55, false, false, 270)
+        );
+        assert_eq!(
+            calc_timing(Hertz(36_000_000), mode),
+            timing(36, 37, false, false, 180)
+        );
+        assert_eq!(
+            calc_timing(Hertz(8_000_000), Mode::standard(Hertz(50_000))),
+            timing(8, 9, false, false, 80)
+        );
+    }
+
+    #[test]
+    fn fast_mode_timing() {
+        let mode = Mode::fast(Hertz(400_000));
+        assert_eq!(
+            calc_timing(Hertz(54_000_000), mode),
+            timing(54, 17, true, false, 45)
+        );
+        assert_eq!(
+            calc_timing(Hertz(36_000_000), mode),
+            timing(36, 11, true, false, 30)
+        );
+        let mode = Mode::Fast {
+            frequency: Hertz(400_000),
+            duty_cycle: DutyCycle::Ratio16to9,
+        };
+        assert_eq!(
+            calc_timing(Hertz(36_000_000), mode),
+            timing(36, 11, true, true, 3)
+        );
+    }
+
+    #[test]
+    fn fast_mode_minimum_clkc() {
+        let mode = Mode::Fast {
+            frequency: Hertz(400_000),
+            duty_cycle: DutyCycle::Ratio16to9,
+        };
+        assert_eq!(
+            calc_timing(Hertz(8_000_000), mode),
+            timing(8, 3, true, true, 1)
+        );
+        assert_eq!(
+            calc_timing(Hertz(2_000_000), Mode::fast(Hertz(400_000))),
+            timing(2, 1, true, false, 1)
+        );
+    }
+}
@@ -0,0 +1,553 @@
+//! Inter-IC Sound (I2S) audio interface
+//!
+//! SPI1 and SPI2 could be configured as I2S1 and I2S2 to stream audio data.
+//! The I2S clock (CK_I2S) is configured by `rcu::Strict::ck_i2s` and is CK_SYS
+//! by default; the sample rate is derived from this clock using the I2S
+//! prescaler calculated by this module.
+//!
+//! Ref: Section 18.4, the User Manual
+
+use crate::gpio::gpioa::PA15;
+use crate::gpio::gpiob::{PB12, PB13, PB15, PB3, PB5};
+use crate::gpio::gpioc::{PC6, PC7};
+use crate::gpio::{Alternate, Floating, Input, PushPull};
+use crate::pac::{SPI1, SPI2};
+use crate::rcu::{Clocks, APB1};
+use crate::unit::Hertz;
+
+/// I2S error
+#[derive(Debug)]
+pub enum Error {
+    /// Transmit buffer is empty when a new frame should be sent (slave mode)
+    Underrun,
+    /// New data is received while receive buffer is not empty
+    Overrun,
+    /// Frame error, the WS signal changed unexpectedly (slave mode)
+    Frame,
+}
+
+/// I2S operation mode
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Slave transmission
+    SlaveTransmit,
+    /// Slave reception
+    SlaveReceive,
+    /// Master transmission
+    MasterTransmit,
+    /// Master reception
+    MasterReceive,
+}
+
+impl Mode {
+    #[inline]
+    fn bits(self) -> u8 {
+        match self {
+            Mode::SlaveTransmit => 0b00,
+            Mode::SlaveReceive => 0b01,
+            Mode::MasterTransmit => 0b10,
+            Mode::MasterReceive => 0b11,
+        }
+    }
+
+    #[inline]
+    fn is_master(self) -> bool {
+        matches!(self, Mode::MasterTransmit | Mode::MasterReceive)
+    }
+}
+
+/// I2S communication standard
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Standard {
+    /// I2S Phillips standard
+    Philips,
+    /// MSB justified standard (left justified)
+    Msb,
+    /// LSB justified standard (right justified)
+    Lsb,
+    /// PCM standard with short frame synchronization
+    PcmShort,
+    /// PCM standard with long frame synchronization
+    PcmLong,
+}
+
+impl Standard {
+    // (I2SSTD, PCMSMOD)
+    #[inline]
+    fn config(self) -> (u8, bool) {
+        match self {
+            Standard::Philips => (0b00, false),
+            Standard::Msb => (0b01, false),
+            Standard::Lsb => (0b10, false),
+            Standard::PcmShort => (0b11, false),
+            Standard::PcmLong => (0b11, true),
+        }
+    }
+}
+
+/// I2S data and channel length
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DataFormat {
+    /// 16-bit data in 16-bit channel frame
+    Data16Channel16,
+    /// 16-bit data in 32-bit channel frame
+    Data16Channel32,
+    /// 24-bit data in 32-bit channel frame
+    Data24Channel32,
+    /// 32-bit data in 32-bit channel frame
+    Data32Channel32,
+}
+
+impl DataFormat {
+    // (DTLEN, CHLEN)
+    #[inline]
+    fn config(self) -> (u8, bool) {
+        match self {
+            DataFormat::Data16Channel16 => (0b00, false),
+            DataFormat::Data16Channel32 => (0b00, true),
+            DataFormat::Data24Channel32 => (0b01, true),
+            DataFormat::Data32Channel32 => (0b10, true),
+        }
+    }
+
+    #[inline]
+    fn is_channel_32bit(self) -> bool {
+        self.config().1
+    }
+
+    #[inline]
+    fn is_data_16bit(self) -> bool {
+        self.config().0 == 0b00
+    }
+}
+
+/// I2S config
+pub struct Config {
+    pub mode: Mode,
+    pub standard: Standard,
+    pub data_format: DataFormat,
+    /// Audio sample rate, only used in master mode
+    pub sample_rate: Hertz,
+    /// Whether the master clock (MCK) should be output
+    pub master_clock: bool,
+    /// Whether the idle state of clock line is high
+    pub clock_idle_high: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            mode: Mode::MasterTransmit,
+            standard: Standard::Philips,
+            data_format: DataFormat::Data16Channel16,
+            sample_rate: Hertz(48_000),
+            master_clock: false,
+            clock_idle_high: false,
+        }
+    }
+}
+
+impl Config {
+    pub fn mode(mut self, mode: Mode) -> Config {
+        self.mode = mode;
+        self
+    }
+
+    pub fn standard(mut self, standard: Standard) -> Config {
+        self.standard = standard;
+        self
+    }
+
+    pub fn data_format(mut self, data_format: DataFormat) -> Config {
+        self.data_format = data_format;
+        self
+    }
+
+    pub fn sample_rate(mut self, sample_rate: impl Into<Hertz>) -> Config {
+        self.sample_rate = sample_rate.into();
+        self
+    }
+
+    pub fn master_clock(mut self, master_clock: bool) -> Config {
+        self.master_clock = master_clock;
+        self
+    }
+
+    pub fn clock_idle_high(mut self, clock_idle_high: bool) -> Config {
+        self.clock_idle_high = clock_idle_high;
+        self
+    }
+}
+
+// Number of CK_I2S cycles per sample for each (2 * DIV + OF) unit.
+//
+// When MCK is output, MCK = 256 * Fs, thus the factor is always 256; otherwise
+// the bit clock is 32 * Fs or 64 * Fs according to the channel length.
+#[inline]
+fn cycles_per_sample(channel_32bit: bool, master_clock: bool) -> u32 {
+    if master_clock {
+        256
+    } else if channel_32bit {
+        64
+    } else {
+        32
+    }
+}
+
+/// Calculates the I2S prescaler `(DIV, OF)` for the nearest possible sample rate.
+///
+/// The sample rate is `ck_i2s / (cycles * (2 * DIV + OF))`, where `cycles` is 256
+/// if MCK is output, or 64 (32-bit channel) or 32 (16-bit channel) otherwise.
+/// Returns `None` if the sample rate cannot be reached, as DIV must be in range
+/// `[2, 255]`.
+///
+/// Ref: Section 18.4.3, the User Manual
+pub fn calc_prescaler(
+    ck_i2s: Hertz,
+    sample_rate: Hertz,
+    channel_32bit: bool,
+    master_clock: bool,
+) -> Option<(u8, bool)> {
+    let cycles = cycles_per_sample(channel_32bit, master_clock) as u64;
+    let denominator = cycles * sample_rate.0 as u64;
+    if denominator == 0 {
+        return None;
+    }
+    // round to the nearest value
+    let x = (ck_i2s.0 as u64 + denominator / 2) / denominator;
+    let (div, of) = (x / 2, x % 2 == 1);
+    if !(2..=255).contains(&div) {
+        return None;
+    }
+    Some((div as u8, of))
+}
+
+/// Returns the actual sample rate for the given I2S prescaler
+pub fn sample_rate(
+    ck_i2s: Hertz,
+    div: u8,
+    of: bool,
+    channel_32bit: bool,
+    master_clock: bool,
+) -> Hertz {
+    let cycles = cycles_per_sample(channel_32bit, master_clock);
+    Hertz(ck_i2s.0 / (cycles * (2 * div as u32 + of as u32)))
+}
+
+#[doc(hidden)]
+mod private {
+    pub trait Sealed {}
+}
+
+/// Word select (WS) pin
+pub trait WsPin<SPI>: private::Sealed {}
+/// Serial clock (CK) pin
+pub trait CkPin<SPI>: private::Sealed {}
+/// Serial data (SD) pin
+pub trait SdPin<SPI>: private::Sealed {}
+/// Master clock (MCK) pin
+pub trait MckPin<SPI>: private::Sealed {}
+
+/// Placeholder for the MCK pin when master clock is not output
+pub struct NoMck;
+
+impl private::Sealed for NoMck {}
+impl<SPI> MckPin<SPI> for NoMck {}
+
+// WS and CK are outputs in master mode and inputs in slave mode; SD is output
+// when transmitting and input when receiving.
+macro_rules! pins {
+    ($spi:ident, WS: $ws:ident, CK: $ck:ident, SD: $sd:ident, MCK: $mck:ident) => {
+        impl private::Sealed for $ws<Alternate<PushPull>> {}
+        impl WsPin<$spi> for $ws<Alternate<PushPull>> {}
+        impl private::Sealed for $ws<Input<Floating>> {}
+        impl WsPin<$spi> for $ws<Input<Floating>> {}
+
+        impl private::Sealed for $ck<Alternate<PushPull>> {}
+        impl CkPin<$spi> for $ck<Alternate<PushPull>> {}
+        impl private::Sealed for $ck<Input<Floating>> {}
+        impl CkPin<$spi> for $ck<Input<Floating>> {}
+
+        impl private::Sealed for $sd<Alternate<PushPull>> {}
+        impl SdPin<$spi> for $sd<Alternate<PushPull>> {}
+        impl private::Sealed for $sd<Input<Floating>> {}
+        impl SdPin<$spi> for $sd<Input<Floating>> {}
+
+        impl private::Sealed for $mck<Alternate<PushPull>> {}
+        impl MckPin<$spi> for $mck<Alternate<PushPull>> {}
+    };
+}
+
+pins!(SPI1, WS: PB12, CK: PB13, SD: PB15, MCK: PC6);
+pins!(SPI2, WS: PA15, CK: PB3, SD: PB5, MCK: PC7);
+
+/// I2S object
+pub struct I2s<SPI, PINS> {
+    spi: SPI,
+    pins: PINS,
+    data_format: DataFormat,
+}
+
+macro_rules! i2s {
+    ($($SPIX:ident: ($i2sX:ident, $spiXen:ident, $spiXrst:ident),)+) => {
+        $(
+            impl<WS, CK, SD, MCK> I2s<$SPIX, (WS, CK, SD, MCK)> {
+                /// Configures the SPI peripheral to operate in I2S mode.
+                ///
+                /// # Panics
+                ///
+                /// In master mode, if the sample rate cannot be reached using the
+                /// frozen CK_I2S clock, this function panics.
+                pub fn $i2sX(
+                    spi: $SPIX,
+                    pins: (WS, CK, SD, MCK),
+                    config: Config,
+                    clocks: Clocks,
+                    apb1: &mut APB1,
+                ) -> Self
+                where
+                    WS: WsPin<$SPIX>,
+                    CK: CkPin<$SPIX>,
+                    SD: SdPin<$SPIX>,
+                    MCK: MckPin<$SPIX>,
+                {
+                    let (i2sstd, pcmsmod) = config.standard.config();
+                    let (dtlen, chlen) = config.data_format.config();
+                    let (div, of) = if config.mode.is_master() {
+                        calc_prescaler(
+                            clocks.ck_i2s(),
+                            config.sample_rate,
+                            chlen,
+                            config.master_clock,
+                        )
+                        .expect("impossible sample rate")
+                    } else {
+                        (2, false) // not used in slave mode
+                    };
+                    riscv::interrupt::free(|_| {
+                        apb1.en().modify(|_, w| w.$spiXen().set_bit());
+                        apb1.rst().modify(|_, w| w.$spiXrst().set_bit());
+                        apb1.rst().modify(|_, w| w.$spiXrst().clear_bit());
+                    });
+                    spi.i2spsc.write(|w| unsafe {
+                        w.div()
+                            .bits(div)
+                            .of()
+                            .bit(of)
+                            .mckoen()
+                            .bit(config.master_clock && config.mode.is_master())
+                    });
+                    spi.i2sctl.write(|w| unsafe {
+                        w.i2ssel()
+                            .set_bit()
+                            .i2sopmod()
+                            .bits(config.mode.bits())
+                            .i2sstd()
+                            .bits(i2sstd)
+                            .pcmsmod()
+                            .bit(pcmsmod)
+                            .ckpl()
+                            .bit(config.clock_idle_high)
+                            .dtlen()
+                            .bits(dtlen)
+                            .chlen()
+                            .bit(chlen)
+                    });
+                    spi.i2sctl.modify(|_, w| w.i2sen().set_bit());
+                    I2s {
+                        spi,
+                        pins,
+                        data_format: config.data_format,
+                    }
+                }
+            }
+
+            impl<PINS> I2s<$SPIX, PINS> {
+                /// Returns the actual sample rate in master mode
+                pub fn sample_rate(&self, clocks: Clocks) -> Hertz {
+                    let psc = self.spi.i2spsc.read();
+                    sample_rate(
+                        clocks.ck_i2s(),
+                        psc.div().bits(),
+                        psc.of().bit(),
+                        self.data_format.is_channel_32bit(),
+                        psc.mckoen().bit(),
+                    )
+                }
+
+                /// Power down and return ownership of owned registers
+                pub fn release(self, apb1: &mut APB1) -> ($SPIX, PINS) {
+                    self.spi.i2sctl.modify(|_, w| w.i2sen().clear_bit());
+                    apb1.en().modify(|_, w| w.$spiXen().clear_bit());
+                    (self.spi, self.pins)
+                }
+
+                #[inline]
+                fn check_errors(&self) -> Result<(), Error> {
+                    let stat = self.spi.stat.read();
+                    if stat.rxorerr().bit_is_set() {
+                        // clear the flag by reading DATA then STAT
+                        let _ = self.spi.data.read();
+                        let _ = self.spi.stat.read();
+                        Err(Error::Overrun)
+                    } else if stat.txurerr().bit_is_set() {
+                        // cleared by reading STAT
+                        Err(Error::Underrun)
+                    } else if stat.ferr().bit_is_set() {
+                        // cleared by reading STAT
+                        Err(Error::Frame)
+                    } else {
+                        Ok(())
+                    }
+                }
+
+                /// Sends one 16-bit half word.
+                ///
+                /// Each 24-bit or 32-bit sample is sent as two half words, higher
+                /// half word first. Returns `true` in the `Ok` variant if this half
+                /// word starts the right channel.
+                pub fn try_send(&mut self, half_word: u16) -> nb::Result<bool, Error> {
+                    self.check_errors()?;
+                    let stat = self.spi.stat.read();
+                    if stat.tbe().bit_is_clear() {
+                        return Err(nb::Error::WouldBlock);
+                    }
+                    let right = stat.i2sch().bit_is_set();
+                    self.spi.data.write(|w| unsafe { w.spi_data().bits(half_word) });
+                    Ok(right)
+                }
+
+                /// Receives one 16-bit half word.
+                ///
+                /// Returns the half word and `true` if it belongs to the right channel.
+                pub fn try_receive(&mut self) -> nb::Result<(u16, bool), Error> {
+                    self.check_errors()?;
+                    let stat = self.spi.stat.read();
+                    if stat.rbne().bit_is_clear() {
+                        return Err(nb::Error::WouldBlock);
+                    }
+                    let right = stat.i2sch().bit_is_set();
+                    Ok((self.spi.data.read().spi_data().bits(), right))
+                }
+
+                /// Writes one stereo frame with left and right samples.
+                ///
+                /// For 16-bit data, only the lower 16 bits of the samples are sent;
+                /// 24-bit data should be left aligned in the 32-bit sample.
+                pub fn write_frame(&mut self, left: u32, right: u32) -> Result<(), Error> {
+                    for &sample in [left, right].iter() {
+                        if self.data_format.is_data_16bit() {
+                            nb::block!(self.try_send(sample as u16))?;
+                        } else {
+                            nb::block!(self.try_send((sample >> 16) as u16))?;
+                            nb::block!(self.try_send(sample as u16))?;
+                        }
+                    }
+                    Ok(())
+                }
+
+                /// Reads one stereo frame, returns left and right samples.
+                ///
+                /// This function synchronizes to the start of left channel before
+                /// reading, thus it drops data until a right channel half word is
+                /// received.
+                pub fn read_frame(&mut self) -> Result<(u32, u32), Error> {
+                    let half_words = if self.data_format.is_data_16bit() { 1 } else { 2 };
+                    // skip until the last half word of right channel
+                    loop {
+                        let (_, right) = nb::block!(self.try_receive())?;
+                        if right {
+                            break;
+                        }
+                    }
+                    let mut samples = [0u32; 2];
+                    let mut idx = 0;
+                    while idx < 2 * half_words {
+                        let (half_word, right) = nb::block!(self.try_receive())?;
+                        // still in the right channel of previous frame
+                        if idx == 0 && right {
+                            continue;
+                        }
+                        let sample = &mut samples[idx / half_words];
+                        *sample = (*sample << 16) | half_word as u32;
+                        idx += 1;
+                    }
+                    Ok((samples[0], samples[1]))
+                }
+            }
+        )+
+    };
+}
+
+i2s! {
+    SPI1: (i2s1, spi1en, spi1rst),
+    SPI2: (i2s2, spi2en, spi2rst),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATES: [u32; 5] = [8_000, 16_000, 44_100, 48_000, 96_000];
+
+    // error of the rate reached, in parts per million
+    fn error_ppm(ck_i2s: Hertz, rate: u32, master_clock: bool) -> u32 {
+        let (div, of) = calc_prescaler(ck_i2s, Hertz(rate), false, master_clock).unwrap();
+        let actual = sample_rate(ck_i2s, div, of, false, master_clock);
+        (actual.0.abs_diff(rate) as u64 * 1_000_000 / rate as u64) as u32
+    }
+
+    #[test]
+    fn standard_rates_without_master_clock() {
+        // CK_I2S from PLL2: 8 MHz HXTAL / 2 * 12 * 2
+        for &rate in RATES.iter() {
+            assert!(error_ppm(Hertz(96_000_000), rate, false) < 10_000);
+        }
+        // exactly reached
+        assert_eq!(
+            calc_prescaler(Hertz(96_000_000), Hertz(8_000), false, false),
+            Some((187, true))
+        );
+        assert_eq!(
+            sample_rate(Hertz(96_000_000), 187, true, false, false).0,
+            8_000
+        );
+    }
+
+    #[test]
+    fn standard_rates_with_master_clock() {
+        // MCK is 256 * Fs, so the prescaler is coarse for high rates
+        for &rate in RATES.iter() {
+            assert!(error_ppm(Hertz(96_000_000), rate, true) < 60_000);
+        }
+        assert!(error_ppm(Hertz(86_000_000), 48_000, true) < 1_000);
+    }
+
+    #[test]
+    fn channel_length() {
+        let (div, of) = calc_prescaler(Hertz(96_000_000), Hertz(48_000), true, false).unwrap();
+        assert_eq!((div, of), (15, true));
+        assert_eq!(
+            sample_rate(Hertz(96_000_000), div, of, true, false).0,
+            48_387
+        );
+    }
+
+    #[test]
+    fn unreachable_rates() {
+        // DIV would be less than 2
+        assert_eq!(
+            calc_prescaler(Hertz(72_000_000), Hertz(96_000), false, true),
+            None
+        );
+        // DIV would be more than 255
+        assert_eq!(
+            calc_prescaler(Hertz(108_000_000), Hertz(4_000), false, false),
+            None
+        );
+        assert_eq!(
+            calc_prescaler(Hertz(96_000_000), Hertz(0), false, false),
+            None
+        );
+    }
+}
@@ -49,14 +49,24 @@ pub use gd32vf103_pac as pac;
 pub mod adc;
 pub mod afio;
 pub mod backup;
+pub mod calendar;
+pub mod capture;
+pub mod compare;
 pub mod crc;
 pub mod ctimer;
 pub mod debug;
 pub mod delay;
+pub mod dma;
 pub mod esig;
 pub mod fmc;
 pub mod gpio;
+pub mod i2c;
+pub mod i2s;
+pub mod monotonic;
+pub mod pwm;
+pub mod qei;
 pub mod rcu;
+pub mod rtc;
 pub mod serial;
 pub mod spi;
 pub mod timer;
@@ -67,6 +77,7 @@ pub mod wdog;
 pub mod prelude {
     pub use embedded_hal::prelude::*;
     pub use crate::afio::AfioExt as _gd32vf103_hal_afio_AfioExt;
+    pub use crate::dma::DmaExt as _gd32vf103_hal_dma_DmaExt;
     pub use crate::gpio::GpioExt as _gd32vf103_hal_gpio_GpioExt;
     pub use crate::gpio::{Unlock as _gd32vf103_hal_gpio_Unlock, UpTo10MHz, UpTo2MHz, UpTo50MHz};
     pub use crate::rcu::RcuExt as _gd32vf103_hal_rcu_RcuExt;
@@ -0,0 +1,276 @@
+//! Monotonic clock
+//!
+//! `Monotonic` counts time with the 64-bit core timer, which runs at a
+//! quarter of the system clock and does not wrap around in practice.
+//! `Instant` and `Duration` are in core timer ticks; `Monotonic` converts
+//! them from and into units of time.
+//!
+//! With the `rtic-monotonic` feature, `Monotonic` implements the RTIC
+//! monotonic trait; tasks are scheduled through the core timer compare
+//! value and `eclic_mtip_handler`.
+use crate::ctimer::CoreTimer;
+use crate::rcu::Clocks;
+use crate::unit::{Hertz, MicroSeconds, MilliSeconds};
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+
+/// The core timer counts once every 4 system clock cycles
+pub const CORE_TIMER_DIVIDER: u32 = 4;
+
+/// A point of time, in core timer ticks
+///
+/// Subtracting saturates at zero: an instant minus a later instant is a zero
+/// duration, and an instant minus a longer duration is the zero instant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant {
+    ticks: u64,
+}
+
+/// A span of time, in core timer ticks
+///
+/// Like integers, subtracting a longer duration overflows, which panics in
+/// debug builds; use `Instant::duration_since` for possibly negative spans.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Duration {
+    ticks: u64,
+}
+
+impl Instant {
+    /// Instant of the given core timer value
+    pub const fn from_ticks(ticks: u64) -> Instant {
+        Instant { ticks }
+    }
+
+    /// Core timer value of this instant
+    pub const fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Time elapsed from `earlier` to this instant, or zero if `earlier` is
+    /// later than this instant
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_ticks(self.ticks.saturating_sub(earlier.ticks))
+    }
+
+    /// Returns `None` if the result overflows
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        self.ticks
+            .checked_add(duration.ticks)
+            .map(Instant::from_ticks)
+    }
+
+    /// Returns `None` if the result underflows
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        self.ticks
+            .checked_sub(duration.ticks)
+            .map(Instant::from_ticks)
+    }
+}
+
+impl Duration {
+    /// Duration of the given count of core timer ticks
+    pub const fn from_ticks(ticks: u64) -> Duration {
+        Duration { ticks }
+    }
+
+    /// Count of core timer ticks of this duration
+    pub const fn ticks(&self) -> u64 {
+        self.ticks
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        Instant::from_ticks(self.ticks + rhs.ticks)
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, rhs: Duration) {
+        self.ticks += rhs.ticks;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, rhs: Duration) -> Instant {
+        Instant::from_ticks(self.ticks.saturating_sub(rhs.ticks))
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, rhs: Duration) {
+        *self = *self - rhs;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Duration {
+        self.duration_since(rhs)
+    }
+}
+
+impl Add for Duration {
+    type Output = Duration;
+
+    fn add(self, rhs: Duration) -> Duration {
+        Duration::from_ticks(self.ticks + rhs.ticks)
+    }
+}
+
+impl AddAssign for Duration {
+    fn add_assign(&mut self, rhs: Duration) {
+        self.ticks += rhs.ticks;
+    }
+}
+
+impl Sub for Duration {
+    type Output = Duration;
+
+    fn sub(self, rhs: Duration) -> Duration {
+        Duration::from_ticks(self.ticks - rhs.ticks)
+    }
+}
+
+impl SubAssign for Duration {
+    fn sub_assign(&mut self, rhs: Duration) {
+        self.ticks -= rhs.ticks;
+    }
+}
+
+/// Core timer as a monotonic clock
+pub struct Monotonic {
+    ctimer: CoreTimer,
+    frequency: Hertz,
+}
+
+// Number of ticks at `frequency` in `count` units of which there are
+// `per_second` in a second, rounded up
+fn ticks_ceil(frequency: Hertz, count: u32, per_second: u64) -> u64 {
+    (count as u64 * frequency.0 as u64).div_ceil(per_second)
+}
+
+impl Monotonic {
+    /// Configures the core timer as a monotonic clock; the timer is not
+    /// reset, so instants keep counting from its current value
+    pub fn new(ctimer: CoreTimer, clocks: Clocks) -> Self {
+        Monotonic {
+            ctimer,
+            frequency: Hertz(clocks.ck_sys().0 / CORE_TIMER_DIVIDER),
+        }
+    }
+
+    /// Release and return the ownership of the core timer resource
+    pub fn free(self) -> CoreTimer {
+        self.ctimer
+    }
+
+    /// Returns the current instant
+    pub fn now(&self) -> Instant {
+        Instant::from_ticks(self.ctimer.get_value())
+    }
+
+    /// Returns the time elapsed since `earlier`
+    pub fn elapsed(&self, earlier: Instant) -> Duration {
+        self.now().duration_since(earlier)
+    }
+
+    /// Counting frequency of the core timer
+    pub fn frequency(&self) -> Hertz {
+        self.frequency
+    }
+
+    /// Converts microseconds into a duration, rounding up to whole ticks
+    pub fn micros(&self, us: impl Into<MicroSeconds>) -> Duration {
+        Duration::from_ticks(ticks_ceil(self.frequency, us.into().0, 1_000_000))
+    }
+
+    /// Converts milliseconds into a duration, rounding up to whole ticks
+    pub fn millis(&self, ms: impl Into<MilliSeconds>) -> Duration {
+        Duration::from_ticks(ticks_ceil(self.frequency, ms.into().0, 1_000))
+    }
+
+    /// Converts a duration into whole microseconds
+    pub fn to_micros(&self, duration: Duration) -> u64 {
+        // split to avoid overflows of long durations
+        let freq = self.frequency.0 as u64;
+        let secs = duration.ticks / freq;
+        let rem = duration.ticks % freq;
+        secs * 1_000_000 + rem * 1_000_000 / freq
+    }
+
+    /// Converts a duration into whole milliseconds
+    pub fn to_millis(&self, duration: Duration) -> u64 {
+        let freq = self.frequency.0 as u64;
+        let secs = duration.ticks / freq;
+        let rem = duration.ticks % freq;
+        secs * 1_000 + rem * 1_000 / freq
+    }
+}
+
+#[cfg(feature = "rtic-monotonic")]
+impl rtic_monotonic::Monotonic for Monotonic {
+    type Instant = Instant;
+    type Duration = Duration;
+
+    fn now(&mut self) -> Instant {
+        Monotonic::now(self)
+    }
+
+    fn set_compare(&mut self, instant: Instant) {
+        self.ctimer.set_compare(instant.ticks);
+    }
+
+    fn clear_compare_flag(&mut self) {
+        // the timer interrupt is pending until the compare value is raised
+        self.ctimer.set_compare(u64::MAX);
+    }
+
+    fn zero() -> Instant {
+        Instant::from_ticks(0)
+    }
+
+    unsafe fn reset(&mut self) {
+        self.ctimer.set_value(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instant_sub_saturates() {
+        let earlier = Instant::from_ticks(100);
+        let later = Instant::from_ticks(250);
+        assert_eq!(later - earlier, Duration::from_ticks(150));
+        assert_eq!(earlier - later, Duration::from_ticks(0));
+        assert_eq!(later - Duration::from_ticks(50), Instant::from_ticks(200));
+        assert_eq!(earlier - Duration::from_ticks(150), Instant::from_ticks(0));
+        let mut instant = earlier;
+        instant -= Duration::from_ticks(u64::MAX);
+        assert_eq!(instant, Instant::from_ticks(0));
+    }
+
+    #[test]
+    fn ticks_round_up() {
+        // core timer of a 108 MHz system clock
+        assert_eq!(ticks_ceil(Hertz(27_000_000), 1, 1_000_000), 27);
+        assert_eq!(ticks_ceil(Hertz(27_000_000), 1, 1_000), 27_000);
+        assert_eq!(ticks_ceil(Hertz(13_500_000), 1, 1_000_000), 14);
+        assert_eq!(ticks_ceil(Hertz(13_500_001), 1, 1_000), 13_501);
+        assert_eq!(ticks_ceil(Hertz(27_000_000), 0, 1_000), 0);
+    }
+
+    #[test]
+    fn ticks_without_overflow() {
+        assert_eq!(
+            ticks_ceil(Hertz(27_000_000), u32::MAX, 1_000),
+            u32::MAX as u64 * 27_000
+        );
+    }
+}
@@ -0,0 +1,888 @@
+//! Pulse Width Modulation (PWM)
+//!
+//! TIMER0 to TIMER4 could each output PWM signals on channel 0 to 3. Pins
+//! are given as a tuple of four, one for each channel; use `NoPin` for
+//! channels without an output pin. All pins should be configured into
+//! `Alternate<PushPull>` mode. The AFIO remap is chosen from the pins:
+//!
+//! | Timer  | Remap      | CH0  | CH1  | CH2  | CH3  |
+//! |:-------|:-----------|:-----|:-----|:-----|:-----|
+//! | TIMER0 | No/Partial | PA8  | PA9  | PA10 | PA11 |
+//! | TIMER0 | Full       | PE9  | PE11 | PE13 | PE14 |
+//! | TIMER1 | No         | PA0  | PA1  | PA2  | PA3  |
+//! | TIMER1 | Partial 1  | PA15 | PB3  | PA2  | PA3  |
+//! | TIMER1 | Partial 2  | PA0  | PA1  | PB10 | PB11 |
+//! | TIMER1 | Full       | PA15 | PB3  | PB10 | PB11 |
+//! | TIMER2 | No         | PA6  | PA7  | PB0  | PB1  |
+//! | TIMER2 | Partial    | PB4  | PB5  | PB0  | PB1  |
+//! | TIMER2 | Full       | PC6  | PC7  | PC8  | PC9  |
+//! | TIMER3 | No         | PB6  | PB7  | PB8  | PB9  |
+//! | TIMER3 | Full       | PD12 | PD13 | PD14 | PD15 |
+//! | TIMER4 | No         | PA0  | PA1  | PA2  | PA3  |
+//!
+//! Pins which need different remaps could not be used together; this is
+//! checked when the PWM is created.
+//!
+//! TIMER0 additionally supports complementary outputs with dead time
+//! insertion and a break input, see `ComplementaryPwm`.
+//!
+//! Ref: Section 15, the User Manual
+
+use crate::afio::PCF0;
+use crate::gpio::gpioa::{PA0, PA1, PA10, PA11, PA15, PA2, PA3, PA6, PA7, PA8, PA9};
+use crate::gpio::gpiob::{
+    PB0, PB1, PB10, PB11, PB12, PB13, PB14, PB15, PB3, PB4, PB5, PB6, PB7, PB8, PB9,
+};
+use crate::gpio::gpioc::{PC6, PC7, PC8, PC9};
+use crate::gpio::gpiod::{PD12, PD13, PD14, PD15};
+use crate::gpio::gpioe::{PE10, PE11, PE12, PE13, PE14, PE15, PE8, PE9};
+use crate::gpio::{Alternate, Floating, Input, PushPull};
+use crate::pac::afio::pcf0;
+use crate::pac::{TIMER0, TIMER1, TIMER2, TIMER3, TIMER4};
+use crate::rcu::{Clocks, APB1, APB2};
+use crate::timer::{calc_psc_car, Period};
+use crate::unit::Hertz;
+use core::convert::Infallible;
+use core::marker::PhantomData;
+use embedded_hal::pwm::PwmPin;
+
+/// Placeholder for a channel without an output pin
+pub struct NoPin;
+
+/// Pin of timer channel 0
+///
+/// `MODE` is `Alternate<PushPull>` for output pins and `Input<Floating>` for
+/// input pins, e.g. in the `capture` module.
+pub trait Ch0<TIMER, MODE = Alternate<PushPull>> {
+    // (mask, bits) of the remap field this pin requires; internal use only
+    #[doc(hidden)]
+    const REMAP: (u8, u8);
+}
+
+/// Pin of timer channel 1
+pub trait Ch1<TIMER, MODE = Alternate<PushPull>> {
+    #[doc(hidden)]
+    const REMAP: (u8, u8);
+}
+
+/// Pin of timer channel 2
+pub trait Ch2<TIMER, MODE = Alternate<PushPull>> {
+    #[doc(hidden)]
+    const REMAP: (u8, u8);
+}
+
+/// Pin of timer channel 3
+pub trait Ch3<TIMER, MODE = Alternate<PushPull>> {
+    #[doc(hidden)]
+    const REMAP: (u8, u8);
+}
+
+impl<TIMER, MODE> Ch0<TIMER, MODE> for NoPin {
+    const REMAP: (u8, u8) = (0, 0);
+}
+
+impl<TIMER, MODE> Ch1<TIMER, MODE> for NoPin {
+    const REMAP: (u8, u8) = (0, 0);
+}
+
+impl<TIMER, MODE> Ch2<TIMER, MODE> for NoPin {
+    const REMAP: (u8, u8) = (0, 0);
+}
+
+impl<TIMER, MODE> Ch3<TIMER, MODE> for NoPin {
+    const REMAP: (u8, u8) = (0, 0);
+}
+
+/// Valid timer channel pins (CH0, CH1, CH2, CH3) in pin mode `MODE`
+pub trait Pins<TIMER, MODE = Alternate<PushPull>> {
+    // private::Sealed; internal use only
+    #[doc(hidden)]
+    const REMAPS: [(u8, u8); 4];
+}
+
+impl<TIMER, MODE, P0, P1, P2, P3> Pins<TIMER, MODE> for (P0, P1, P2, P3)
+where
+    P0: Ch0<TIMER, MODE>,
+    P1: Ch1<TIMER, MODE>,
+    P2: Ch2<TIMER, MODE>,
+    P3: Ch3<TIMER, MODE>,
+{
+    const REMAPS: [(u8, u8); 4] = [P0::REMAP, P1::REMAP, P2::REMAP, P3::REMAP];
+}
+
+// Merge remap requirements of all pins into the remap field value
+pub(crate) fn remap_bits(remaps: &[(u8, u8)]) -> u8 {
+    let (mut mask, mut bits) = (0, 0);
+    for &(pin_mask, pin_bits) in remaps {
+        assert!(
+            (bits ^ pin_bits) & mask & pin_mask == 0,
+            "pins need different remaps"
+        );
+        mask |= pin_mask;
+        bits |= pin_bits & pin_mask;
+    }
+    bits
+}
+
+macro_rules! pins {
+    ($TIMERX:ident, $CH:ident, [$($PXi:ident: ($mask:expr, $bits:expr),)+]) => {
+        $(
+            impl $CH<$TIMERX, Alternate<PushPull>> for $PXi<Alternate<PushPull>> {
+                const REMAP: (u8, u8) = ($mask, $bits);
+            }
+
+            impl $CH<$TIMERX, Input<Floating>> for $PXi<Input<Floating>> {
+                const REMAP: (u8, u8) = ($mask, $bits);
+            }
+        )+
+    };
+}
+
+// TIMER0: 00 no remap, 01 partial remap, 11 full remap
+pins!(TIMER0, Ch0, [PA8: (0b10, 0b00), PE9: (0b11, 0b11),]);
+pins!(TIMER0, Ch1, [PA9: (0b10, 0b00), PE11: (0b11, 0b11),]);
+pins!(TIMER0, Ch2, [PA10: (0b10, 0b00), PE13: (0b11, 0b11),]);
+pins!(TIMER0, Ch3, [PA11: (0b10, 0b00), PE14: (0b11, 0b11),]);
+// TIMER1: bit 0 remaps CH0 and CH1, bit 1 remaps CH2 and CH3
+pins!(TIMER1, Ch0, [PA0: (0b01, 0b00), PA15: (0b01, 0b01),]);
+pins!(TIMER1, Ch1, [PA1: (0b01, 0b00), PB3: (0b01, 0b01),]);
+pins!(TIMER1, Ch2, [PA2: (0b10, 0b00), PB10: (0b10, 0b10),]);
+pins!(TIMER1, Ch3, [PA3: (0b10, 0b00), PB11: (0b10, 0b10),]);
+// TIMER2: 00 no remap, 10 partial remap, 11 full remap
+pins!(TIMER2, Ch0, [PA6: (0b11, 0b00), PB4: (0b11, 0b10), PC6: (0b11, 0b11),]);
+pins!(TIMER2, Ch1, [PA7: (0b11, 0b00), PB5: (0b11, 0b10), PC7: (0b11, 0b11),]);
+pins!(TIMER2, Ch2, [PB0: (0b01, 0b00), PC8: (0b11, 0b11),]);
+pins!(TIMER2, Ch3, [PB1: (0b01, 0b00), PC9: (0b11, 0b11),]);
+// TIMER3: 0 no remap, 1 full remap
+pins!(TIMER3, Ch0, [PB6: (0b1, 0b0), PD12: (0b1, 0b1),]);
+pins!(TIMER3, Ch1, [PB7: (0b1, 0b0), PD13: (0b1, 0b1),]);
+pins!(TIMER3, Ch2, [PB8: (0b1, 0b0), PD14: (0b1, 0b1),]);
+pins!(TIMER3, Ch3, [PB9: (0b1, 0b0), PD15: (0b1, 0b1),]);
+// TIMER4: no remap
+pins!(TIMER4, Ch0, [PA0: (0, 0),]);
+pins!(TIMER4, Ch1, [PA1: (0, 0),]);
+pins!(TIMER4, Ch2, [PA2: (0, 0),]);
+pins!(TIMER4, Ch3, [PA3: (0, 0),]);
+
+/// Timer channel
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    /// Channel 0
+    C0,
+    /// Channel 1
+    C1,
+    /// Channel 2
+    C2,
+    /// Channel 3
+    C3,
+}
+
+/// Type state of channel 0
+pub struct C0;
+/// Type state of channel 1
+pub struct C1;
+/// Type state of channel 2
+pub struct C2;
+/// Type state of channel 3
+pub struct C3;
+
+/// PWM abstraction of a timer
+pub struct Pwm<TIMER, PINS> {
+    timer: TIMER,
+    pins: PINS,
+    clock_frequency: Hertz,
+}
+
+/// A single PWM channel, split from `Pwm`
+pub struct PwmChannel<TIMER, CH> {
+    _timer: PhantomData<TIMER>,
+    _channel: PhantomData<CH>,
+}
+
+impl<TIMER, CH> PwmChannel<TIMER, CH> {
+    // callers must make sure this channel is not used elsewhere
+    fn new() -> Self {
+        PwmChannel {
+            _timer: PhantomData,
+            _channel: PhantomData,
+        }
+    }
+}
+
+// PWM mode 0: output is active while the counter is less than the compare value
+const PWM_MODE_0: u8 = 0b110;
+
+// Break interrupt flag in INTF, which is cleared by writing 0
+const BRKIF: u16 = 1 << 7;
+
+// CAR is kept below 0xFFFF, so that the compare value of 100% duty, CAR + 1,
+// fits in 16 bits
+const MAX_CAR: u16 = 0xFFFE;
+
+macro_rules! pwm {
+    ($($TIMERX:ident: (
+        $timerx:ident, $timerxen:ident, $timerxrst:ident, $APBX:ident, $ck_timerx:ident,
+        $set_remap:expr $(, $cchp:ident)?
+    ),)+) => {
+$(
+impl<PINS> Pwm<$TIMERX, PINS> {
+    /// Power on the timer and configure all four channels into PWM mode.
+    ///
+    /// Outputs are disabled until enabled by `try_enable`.
+    pub fn $timerx(
+        timer: $TIMERX,
+        pins: PINS,
+        pcf0: &mut PCF0,
+        freq: impl Into<Hertz>,
+        clocks: Clocks,
+        apb: &mut $APBX,
+    ) -> Self
+    where
+        PINS: Pins<$TIMERX>,
+    {
+        let remap = remap_bits(&PINS::REMAPS);
+        let mut pwm = Pwm::<$TIMERX, PINS>::init(timer, pins, pcf0, remap, clocks, apb);
+        // advanced timers need the primary output enabled
+        $( pwm.timer.$cchp.modify(|_, w| w.poen().set_bit()); )?
+        pwm.start(freq);
+        pwm
+    }
+
+    // Power on the timer, remap pins and configure all four channels into
+    // PWM mode; the counter is not started
+    fn init(
+        timer: $TIMERX,
+        pins: PINS,
+        pcf0: &mut PCF0,
+        remap: u8,
+        clocks: Clocks,
+        apb: &mut $APBX,
+    ) -> Self {
+        riscv::interrupt::free(|_| {
+            apb.en().modify(|_, w| w.$timerxen().set_bit());
+            apb.rst().modify(|_, w| w.$timerxrst().set_bit());
+            apb.rst().modify(|_, w| w.$timerxrst().clear_bit());
+            pcf0.pcf0().modify(|_, w| {
+                ($set_remap)(w, remap);
+                w
+            });
+        });
+        timer.chctl0_output().write(|w| unsafe {
+            w.ch0comctl().bits(PWM_MODE_0).ch0comsen().set_bit()
+                .ch1comctl().bits(PWM_MODE_0).ch1comsen().set_bit()
+        });
+        timer.chctl1_output().write(|w| unsafe {
+            w.ch2comctl().bits(PWM_MODE_0).ch2comsen().set_bit()
+                .ch3comctl().bits(PWM_MODE_0).ch3comsen().set_bit()
+        });
+        Pwm {
+            timer,
+            pins,
+            clock_frequency: clocks.$ck_timerx(),
+        }
+    }
+
+    // Set the period and start the counter
+    fn start(&mut self, freq: impl Into<Hertz>) {
+        embedded_hal::pwm::Pwm::try_set_period(self, freq).ok();
+        self.timer.ctl0.modify(|_, w| w.arse().set_bit().cen().set_bit());
+    }
+
+    /// Power down the timer and return ownership of owned registers
+    pub fn release(self, apb: &mut $APBX) -> ($TIMERX, PINS) {
+        self.timer.ctl0.modify(|_, w| w.cen().clear_bit());
+        apb.en().modify(|_, w| w.$timerxen().clear_bit());
+        (self.timer, self.pins)
+    }
+
+    /// Split into independent channels; the period could no longer be changed
+    pub fn split(
+        self,
+    ) -> (
+        PwmChannel<$TIMERX, C0>,
+        PwmChannel<$TIMERX, C1>,
+        PwmChannel<$TIMERX, C2>,
+        PwmChannel<$TIMERX, C3>,
+    ) {
+        (
+            PwmChannel::new(),
+            PwmChannel::new(),
+            PwmChannel::new(),
+            PwmChannel::new(),
+        )
+    }
+}
+
+impl<PINS> embedded_hal::pwm::Pwm for Pwm<$TIMERX, PINS> {
+    type Error = Infallible;
+    type Channel = Channel;
+    type Time = Hertz;
+    type Duty = u16;
+
+    fn try_disable(&mut self, channel: Channel) -> Result<(), Self::Error> {
+        with_channel!($TIMERX, channel, ch => ch.try_disable())
+    }
+
+    fn try_enable(&mut self, channel: Channel) -> Result<(), Self::Error> {
+        with_channel!($TIMERX, channel, ch => ch.try_enable())
+    }
+
+    fn try_get_period(&self) -> Result<Hertz, Self::Error> {
+        let psc = self.timer.psc.read().psc().bits() as u32;
+        let car = self.timer.car.read().carl().bits() as u32;
+        Ok(Hertz(self.clock_frequency.0 / (psc + 1) / (car + 1)))
+    }
+
+    fn try_get_duty(&self, channel: Channel) -> Result<u16, Self::Error> {
+        with_channel!($TIMERX, channel, ch => ch.try_get_duty())
+    }
+
+    /// Duty value of 100%; this is the count of timer cycles in a period
+    fn try_get_max_duty(&self) -> Result<u16, Self::Error> {
+        // CAR is at most `MAX_CAR`
+        Ok(self.timer.car.read().carl().bits() + 1)
+    }
+
+    fn try_set_duty(&mut self, channel: Channel, duty: u16) -> Result<(), Self::Error> {
+        with_channel!($TIMERX, channel, ch => ch.try_set_duty(duty))
+    }
+
+    /// Sets the PWM frequency; duty values are not scaled, they should be
+    /// set again according to the new maximum duty.
+    fn try_set_period<P>(&mut self, period: P) -> Result<(), Self::Error>
+    where
+        P: Into<Hertz>,
+    {
+        let ticks = Period::Frequency(period.into()).ticks(self.clock_frequency);
+        let (psc, car) = calc_psc_car(ticks, MAX_CAR);
+        riscv::interrupt::free(|_| {
+            self.timer.psc.write(|w| unsafe { w.psc().bits(psc) });
+            self.timer.car.write(|w| unsafe { w.carl().bits(car) });
+            // load the prescaler and the shadowed registers
+            self.timer.swevg.write(|w| w.upg().set_bit());
+        });
+        Ok(())
+    }
+}
+
+pwm_pin! { $TIMERX: [
+    C0: (ch0en, ch0cv, ch0val),
+    C1: (ch1en, ch1cv, ch1val),
+    C2: (ch2en, ch2cv, ch2val),
+    C3: (ch3en, ch3cv, ch3val),
+] }
+)+
+    };
+}
+
+// Run `$e` on a temporary channel struct, the timer is owned by the caller
+macro_rules! with_channel {
+    ($TIMERX:ident, $channel:expr, $ch:ident => $e:expr) => {
+        match $channel {
+            Channel::C0 => {
+                #[allow(unused_mut)]
+                let mut $ch = PwmChannel::<$TIMERX, C0>::new();
+                $e
+            }
+            Channel::C1 => {
+                #[allow(unused_mut)]
+                let mut $ch = PwmChannel::<$TIMERX, C1>::new();
+                $e
+            }
+            Channel::C2 => {
+                #[allow(unused_mut)]
+                let mut $ch = PwmChannel::<$TIMERX, C2>::new();
+                $e
+            }
+            Channel::C3 => {
+                #[allow(unused_mut)]
+                let mut $ch = PwmChannel::<$TIMERX, C3>::new();
+                $e
+            }
+        }
+    };
+}
+
+macro_rules! pwm_pin {
+    ($TIMERX:ident: [$($CX:ident: ($chxen:ident, $chxcv:ident, $chxval:ident),)+]) => {
+        $(
+            impl embedded_hal::pwm::PwmPin for PwmChannel<$TIMERX, $CX> {
+                type Error = Infallible;
+                type Duty = u16;
+
+                fn try_disable(&mut self) -> Result<(), Self::Error> {
+                    riscv::interrupt::free(|_| unsafe {
+                        (*$TIMERX::ptr()).chctl2.modify(|_, w| w.$chxen().clear_bit())
+                    });
+                    Ok(())
+                }
+
+                fn try_enable(&mut self) -> Result<(), Self::Error> {
+                    riscv::interrupt::free(|_| unsafe {
+                        (*$TIMERX::ptr()).chctl2.modify(|_, w| w.$chxen().set_bit())
+                    });
+                    Ok(())
+                }
+
+                fn try_get_duty(&self) -> Result<u16, Self::Error> {
+                    Ok(unsafe { (*$TIMERX::ptr()).$chxcv.read().$chxval().bits() })
+                }
+
+                /// Duty value of 100%; this is the count of timer cycles in a period
+                fn try_get_max_duty(&self) -> Result<u16, Self::Error> {
+                    let car = unsafe { (*$TIMERX::ptr()).car.read().carl().bits() };
+                    Ok(car + 1)
+                }
+
+                fn try_set_duty(&mut self, duty: u16) -> Result<(), Self::Error> {
+                    unsafe { (*$TIMERX::ptr()).$chxcv.write(|w| w.$chxval().bits(duty)) };
+                    Ok(())
+                }
+            }
+        )+
+    };
+}
+
+pwm! {
+    TIMER0: (timer0, timer0en, timer0rst, APB2, ck_timerx,
+        |w: &mut pcf0::W, bits| unsafe { w.timer0_remap().bits(bits); }, cchp),
+    TIMER1: (timer1, timer1en, timer1rst, APB1, ck_timerx_apb1,
+        |w: &mut pcf0::W, bits| unsafe { w.timer1_remap().bits(bits); }),
+    TIMER2: (timer2, timer2en, timer2rst, APB1, ck_timerx_apb1,
+        |w: &mut pcf0::W, bits| unsafe { w.timer2_remap().bits(bits); }),
+    TIMER3: (timer3, timer3en, timer3rst, APB1, ck_timerx_apb1,
+        |w: &mut pcf0::W, bits| { w.timer3_remap().bit(bits != 0); }),
+    TIMER4: (timer4, timer4en, timer4rst, APB1, ck_timerx_apb1,
+        |_: &mut pcf0::W, _| {}),
+}
+
+/// Complementary output pin of TIMER0 channel 0
+pub trait Ch0N {
+    #[doc(hidden)]
+    const REMAP: (u8, u8);
+}
+
+/// Complementary output pin of TIMER0 channel 1
+pub trait Ch1N {
+    #[doc(hidden)]
+    const REMAP: (u8, u8);
+}
+
+/// Complementary output pin of TIMER0 channel 2
+pub trait Ch2N {
+    #[doc(hidden)]
+    const REMAP: (u8, u8);
+}
+
+/// Break input pin of TIMER0
+pub trait BreakIn {
+    #[doc(hidden)]
+    const REMAP: (u8, u8);
+}
+
+impl Ch0N for NoPin {
+    const REMAP: (u8, u8) = (0, 0);
+}
+
+impl Ch1N for NoPin {
+    const REMAP: (u8, u8) = (0, 0);
+}
+
+impl Ch2N for NoPin {
+    const REMAP: (u8, u8) = (0, 0);
+}
+
+impl BreakIn for NoPin {
+    const REMAP: (u8, u8) = (0, 0);
+}
+
+/// Valid complementary output and break input pins (CH0N, CH1N, CH2N, BRKIN)
+///
+/// Complementary outputs should be configured into `Alternate<PushPull>`
+/// mode, and the break input into `Input<Floating>` mode.
+///
+/// | Remap   | CH0N | CH1N | CH2N | BRKIN |
+/// |:--------|:-----|:-----|:-----|:------|
+/// | No      | PB13 | PB14 | PB15 | PB12  |
+/// | Partial | PA7  | PB0  | PB1  | PA6   |
+/// | Full    | PE8  | PE10 | PE12 | PE15  |
+pub trait ComplementaryPins {
+    // private::Sealed; internal use only
+    #[doc(hidden)]
+    const REMAPS: [(u8, u8); 4];
+}
+
+impl<N0, N1, N2, B> ComplementaryPins for (N0, N1, N2, B)
+where
+    N0: Ch0N,
+    N1: Ch1N,
+    N2: Ch2N,
+    B: BreakIn,
+{
+    const REMAPS: [(u8, u8); 4] = [N0::REMAP, N1::REMAP, N2::REMAP, B::REMAP];
+}
+
+macro_rules! complementary_pins {
+    ($CH:ident, $MODE:ty, [$($PXi:ident: $bits:expr,)+]) => {
+        $(
+            impl $CH for $PXi<$MODE> {
+                const REMAP: (u8, u8) = (0b11, $bits);
+            }
+        )+
+    };
+}
+
+complementary_pins!(Ch0N, Alternate<PushPull>, [PB13: 0b00, PA7: 0b01, PE8: 0b11,]);
+complementary_pins!(Ch1N, Alternate<PushPull>, [PB14: 0b00, PB0: 0b01, PE10: 0b11,]);
+complementary_pins!(Ch2N, Alternate<PushPull>, [PB15: 0b00, PB1: 0b01, PE12: 0b11,]);
+complementary_pins!(BreakIn, Input<Floating>, [PB12: 0b00, PA6: 0b01, PE15: 0b11,]);
+
+/// Active level of the break input
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum BreakPolarity {
+    /// Break when the input is low
+    ActiveLow,
+    /// Break when the input is high
+    ActiveHigh,
+}
+
+/// Lock level of TIMER0 configuration registers (PROT)
+///
+/// The lock level could only be written once after reset.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LockLevel {
+    /// No register is locked
+    Off,
+    /// Dead time, break, off-state idle and automatic output settings are
+    /// locked
+    Level1,
+    /// Level 1, plus polarity and off-state run settings are locked
+    Level2,
+    /// Level 2, plus output compare mode settings are locked
+    Level3,
+}
+
+impl LockLevel {
+    #[inline]
+    fn bits(self) -> u8 {
+        match self {
+            LockLevel::Off => 0b00,
+            LockLevel::Level1 => 0b01,
+            LockLevel::Level2 => 0b10,
+            LockLevel::Level3 => 0b11,
+        }
+    }
+}
+
+/// Complementary PWM config
+pub struct ComplementaryConfig {
+    /// Dead time inserted between a main output and its complementary
+    /// output, in nanoseconds
+    pub dead_time_ns: u32,
+    /// Enable the break input with given polarity
+    pub break_input: Option<BreakPolarity>,
+    /// Enable outputs again automatically at the next update event after
+    /// a break (OAEN)
+    pub automatic_output: bool,
+    /// Drive disabled outputs to inactive level instead of releasing them
+    /// while the timer runs (ROS)
+    pub run_off_state: bool,
+    /// Drive outputs to idle level instead of releasing them after a break
+    /// or when outputs are disabled (IOS)
+    pub idle_off_state: bool,
+    /// Lock level of configuration registers
+    pub lock: LockLevel,
+}
+
+impl Default for ComplementaryConfig {
+    fn default() -> Self {
+        ComplementaryConfig {
+            dead_time_ns: 0,
+            break_input: None,
+            automatic_output: false,
+            run_off_state: false,
+            idle_off_state: false,
+            lock: LockLevel::Off,
+        }
+    }
+}
+
+impl ComplementaryConfig {
+    pub fn dead_time_ns(mut self, dead_time_ns: u32) -> Self {
+        self.dead_time_ns = dead_time_ns;
+        self
+    }
+
+    pub fn break_input(mut self, polarity: BreakPolarity) -> Self {
+        self.break_input = Some(polarity);
+        self
+    }
+
+    pub fn automatic_output(mut self, enable: bool) -> Self {
+        self.automatic_output = enable;
+        self
+    }
+
+    pub fn run_off_state(mut self, enable: bool) -> Self {
+        self.run_off_state = enable;
+        self
+    }
+
+    pub fn idle_off_state(mut self, enable: bool) -> Self {
+        self.idle_off_state = enable;
+        self
+    }
+
+    pub fn lock(mut self, lock: LockLevel) -> Self {
+        self.lock = lock;
+        self
+    }
+}
+
+/// Calculate the DTCFG field value for a dead time of at least `ns`
+/// nanoseconds with the timer clock `clock`.
+///
+/// With t = 1 / clock, DTCFG encodes the dead time as:
+///
+/// | DTCFG\[7:5\] | Dead time                      | Range in t   |
+/// |:-------------|:-------------------------------|:-------------|
+/// | 0xx          | DTCFG\[7:0\] * t               | 0 to 127     |
+/// | 10x          | (64 + DTCFG\[5:0\]) * 2 * t    | 128 to 254   |
+/// | 110          | (32 + DTCFG\[4:0\]) * 8 * t    | 256 to 504   |
+/// | 111          | (32 + DTCFG\[4:0\]) * 16 * t   | 512 to 1008  |
+///
+/// The dead time is rounded up to the next value that could be encoded, and
+/// saturates at 1008 t.
+pub fn calc_dtcfg(clock: Hertz, ns: u32) -> u8 {
+    // ceil(ns * clock / 10^9) timer clock cycles
+    let ticks = (ns as u64 * clock.0 as u64).div_ceil(1_000_000_000);
+    if ticks <= 127 {
+        ticks as u8
+    } else if ticks <= 254 {
+        0b1000_0000 | (ticks.div_ceil(2) - 64) as u8
+    } else if ticks <= 504 {
+        0b1100_0000 | (ticks.div_ceil(8) - 32) as u8
+    } else if ticks <= 1008 {
+        0b1110_0000 | (ticks.div_ceil(16) - 32) as u8
+    } else {
+        0xFF
+    }
+}
+
+/// PWM on TIMER0 with complementary outputs, dead time and break input
+///
+/// Channel 0 to 2 drive both main and complementary outputs; channel 3 has
+/// only the main output.
+///
+/// When the break input is active, all outputs are driven to their idle
+/// states by hardware. To handle the break in software, call `listen_break`
+/// and define `TIMER0_BRK_IRQHandler`, which should call
+/// `clear_break_interrupt_flag`.
+pub struct ComplementaryPwm<PINS, NPINS> {
+    pwm: Pwm<TIMER0, PINS>,
+    npins: NPINS,
+}
+
+impl<PINS, NPINS> ComplementaryPwm<PINS, NPINS> {
+    /// Power on TIMER0 and configure all four channels into PWM mode; `pins`
+    /// are the main and the complementary output pins.
+    ///
+    /// Outputs are disabled until enabled by `try_enable`.
+    pub fn timer0(
+        timer: TIMER0,
+        pins: (PINS, NPINS),
+        pcf0: &mut PCF0,
+        freq: impl Into<Hertz>,
+        config: ComplementaryConfig,
+        clocks: Clocks,
+        apb2: &mut APB2,
+    ) -> Self
+    where
+        PINS: Pins<TIMER0>,
+        NPINS: ComplementaryPins,
+    {
+        let (pins, npins) = pins;
+        let mut remaps = [(0, 0); 8];
+        remaps[..4].copy_from_slice(&PINS::REMAPS);
+        remaps[4..].copy_from_slice(&NPINS::REMAPS);
+        let remap = remap_bits(&remaps);
+        let mut pwm = Pwm::<TIMER0, PINS>::init(timer, pins, pcf0, remap, clocks, apb2);
+        let dtcfg = calc_dtcfg(pwm.clock_frequency, config.dead_time_ns);
+        // PROT could only be written once, so CCHP is written in one go
+        pwm.timer.cchp.write(|w| unsafe {
+            w.dtcfg()
+                .bits(dtcfg)
+                .brken()
+                .bit(config.break_input.is_some())
+                .brkp()
+                .bit(config.break_input == Some(BreakPolarity::ActiveHigh))
+                .oaen()
+                .bit(config.automatic_output)
+                .ros()
+                .bit(config.run_off_state)
+                .ios()
+                .bit(config.idle_off_state)
+                .prot()
+                .bits(config.lock.bits())
+                .poen()
+                .set_bit()
+        });
+        pwm.start(freq);
+        ComplementaryPwm { pwm, npins }
+    }
+
+    /// Power down TIMER0 and return ownership of owned registers
+    pub fn release(self, apb2: &mut APB2) -> (TIMER0, PINS, NPINS) {
+        let (timer, pins) = self.pwm.release(apb2);
+        (timer, pins, self.npins)
+    }
+
+    /// Enables all outputs (POEN)
+    pub fn enable_outputs(&mut self) {
+        self.pwm.timer.cchp.modify(|_, w| w.poen().set_bit());
+    }
+
+    /// Disables all outputs (POEN); outputs go to their idle states
+    pub fn disable_outputs(&mut self) {
+        self.pwm.timer.cchp.modify(|_, w| w.poen().clear_bit());
+    }
+
+    /// Checks if outputs are enabled; a break disables them
+    pub fn is_outputs_enabled(&self) -> bool {
+        self.pwm.timer.cchp.read().poen().bit_is_set()
+    }
+
+    /// Enables the break interrupt, `TIMER0_BRK_IRQHandler`
+    pub fn listen_break(&mut self) {
+        self.pwm.timer.dmainten.modify(|_, w| w.brkie().set_bit());
+    }
+
+    /// Disables the break interrupt
+    pub fn unlisten_break(&mut self) {
+        self.pwm.timer.dmainten.modify(|_, w| w.brkie().clear_bit());
+    }
+
+    /// Checks if the break interrupt flag is set
+    pub fn is_break_pending(&self) -> bool {
+        self.pwm.timer.intf.read().brkif().bit_is_set()
+    }
+
+    /// Clears the break interrupt flag; outputs stay disabled until
+    /// `enable_outputs` or the automatic output enable
+    pub fn clear_break_interrupt_flag(&mut self) {
+        // write other flags as 1 to keep them
+        self.pwm.timer.intf.write(|w| unsafe { w.bits(!BRKIF) });
+    }
+}
+
+impl<PINS, NPINS> embedded_hal::pwm::Pwm for ComplementaryPwm<PINS, NPINS> {
+    type Error = Infallible;
+    type Channel = Channel;
+    type Time = Hertz;
+    type Duty = u16;
+
+    /// Disables both main and complementary outputs of the channel
+    fn try_disable(&mut self, channel: Channel) -> Result<(), Self::Error> {
+        self.pwm.timer.chctl2.modify(|_, w| match channel {
+            Channel::C0 => w.ch0en().clear_bit().ch0nen().clear_bit(),
+            Channel::C1 => w.ch1en().clear_bit().ch1nen().clear_bit(),
+            Channel::C2 => w.ch2en().clear_bit().ch2nen().clear_bit(),
+            Channel::C3 => w.ch3en().clear_bit(),
+        });
+        Ok(())
+    }
+
+    /// Enables both main and complementary outputs of the channel
+    fn try_enable(&mut self, channel: Channel) -> Result<(), Self::Error> {
+        self.pwm.timer.chctl2.modify(|_, w| match channel {
+            Channel::C0 => w.ch0en().set_bit().ch0nen().set_bit(),
+            Channel::C1 => w.ch1en().set_bit().ch1nen().set_bit(),
+            Channel::C2 => w.ch2en().set_bit().ch2nen().set_bit(),
+            Channel::C3 => w.ch3en().set_bit(),
+        });
+        Ok(())
+    }
+
+    fn try_get_period(&self) -> Result<Hertz, Self::Error> {
+        embedded_hal::pwm::Pwm::try_get_period(&self.pwm)
+    }
+
+    fn try_get_duty(&self, channel: Channel) -> Result<u16, Self::Error> {
+        embedded_hal::pwm::Pwm::try_get_duty(&self.pwm, channel)
+    }
+
+    fn try_get_max_duty(&self) -> Result<u16, Self::Error> {
+        embedded_hal::pwm::Pwm::try_get_max_duty(&self.pwm)
+    }
+
+    fn try_set_duty(&mut self, channel: Channel, duty: u16) -> Result<(), Self::Error> {
+        embedded_hal::pwm::Pwm::try_set_duty(&mut self.pwm, channel, duty)
+    }
+
+    fn try_set_period<P>(&mut self, period: P) -> Result<(), Self::Error>
+    where
+        P: Into<Hertz>,
+    {
+        embedded_hal::pwm::Pwm::try_set_period(&mut self.pwm, period)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn psc_car_leaves_room_for_full_duty() {
+        let ticks = |clock, freq| Period::Frequency(Hertz(freq)).ticks(Hertz(clock));
+        // 65536 cycles would need CAR = 0xFFFF without a prescaler
+        assert_eq!(calc_psc_car(ticks(65_536_000, 1_000), MAX_CAR), (1, 32767));
+        assert_eq!(calc_psc_car(ticks(108_000_000, 1_648), MAX_CAR), (0, 65533));
+        assert_eq!(calc_psc_car(ticks(108_000_000, 1_000), MAX_CAR), (1, 53999));
+        assert_eq!(calc_psc_car(ticks(108_000_000, 1), MAX_CAR), (1727, 62499));
+    }
+
+    #[test]
+    fn dtcfg_in_timer_cycles() {
+        // 1 cycle is 10 ns at 100 MHz
+        let clock = Hertz(100_000_000);
+        assert_eq!(calc_dtcfg(clock, 0), 0);
+        assert_eq!(calc_dtcfg(clock, 10), 1);
+        // rounded up
+        assert_eq!(calc_dtcfg(clock, 11), 2);
+        assert_eq!(calc_dtcfg(clock, 1270), 127);
+    }
+
+    #[test]
+    fn dtcfg_in_2_cycles() {
+        let clock = Hertz(100_000_000);
+        // 128 cycles
+        assert_eq!(calc_dtcfg(clock, 1280), 0b1000_0000);
+        // 129 cycles, rounded up to 130
+        assert_eq!(calc_dtcfg(clock, 1290), 0b1000_0001);
+        // 254 cycles
+        assert_eq!(calc_dtcfg(clock, 2540), 0b1011_1111);
+    }
+
+    #[test]
+    fn dtcfg_in_8_cycles() {
+        let clock = Hertz(100_000_000);
+        // 255 cycles, rounded up to 256
+        assert_eq!(calc_dtcfg(clock, 2550), 0b1100_0000);
+        // 264 cycles
+        assert_eq!(calc_dtcfg(clock, 2640), 0b1100_0001);
+        // 504 cycles
+        assert_eq!(calc_dtcfg(clock, 5040), 0b1101_1111);
+    }
+
+    #[test]
+    fn dtcfg_in_16_cycles() {
+        let clock = Hertz(100_000_000);
+        // 505 cycles, rounded up to 512
+        assert_eq!(calc_dtcfg(clock, 5050), 0b1110_0000);
+        // 1008 cycles
+        assert_eq!(calc_dtcfg(clock, 10_080), 0b1111_1111);
+        // saturated
+        assert_eq!(calc_dtcfg(clock, 20_000), 0xFF);
+        assert_eq!(calc_dtcfg(Hertz(108_000_000), u32::MAX), 0xFF);
+    }
+}
@@ -0,0 +1,247 @@
+//! Quadrature Encoder Interface (QEI)
+//!
+//! TIMER0 to TIMER4 could count the edges of a quadrature encoder on channel
+//! 0 and 1 inputs; the counter counts up or down according to the phase
+//! between the two signals, and wraps around at the auto-reload value.
+//!
+//! Pins should be configured into `Input<Floating>` mode; valid pins and
+//! remaps are the channel 0 and 1 pins in the `pwm` module.
+//!
+//! The 16-bit counter could be extended by software: enable the update
+//! interrupt with `listen_wrap`, call `handle_wrap` in the interrupt handler,
+//! and read the extended count with `count_extended`.
+//!
+//! Ref: Section 15.2.4, the User Manual
+use crate::afio::PCF0;
+use crate::gpio::{Floating, Input};
+use crate::pac::afio::pcf0;
+use crate::pac::{TIMER0, TIMER1, TIMER2, TIMER3, TIMER4};
+use crate::pwm::{remap_bits, Ch0, Ch1};
+use crate::rcu::{APB1, APB2};
+use crate::timer::UPIF;
+use core::convert::Infallible;
+use embedded_hal::qei::Direction;
+
+/// Edges to count on
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Count on CI0 edges only, at twice the encoder line count
+    Ci0,
+    /// Count on CI1 edges only, at twice the encoder line count
+    Ci1,
+    /// Count on both CI0 and CI1 edges, at four times the encoder line count
+    Both,
+}
+
+/// Encoder config
+pub struct Config {
+    pub mode: Mode,
+    /// Digital filter of both inputs, CHxCAPFLT, 0 (off) to 15
+    pub filter: u8,
+    /// Inverts the counting direction
+    pub invert: bool,
+    /// The counter wraps around after this value
+    pub auto_reload: u16,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            mode: Mode::Both,
+            filter: 0,
+            invert: false,
+            auto_reload: u16::MAX,
+        }
+    }
+}
+
+impl Config {
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn filter(mut self, filter: u8) -> Self {
+        assert!(filter < 16);
+        self.filter = filter;
+        self
+    }
+
+    pub fn invert(mut self, invert: bool) -> Self {
+        self.invert = invert;
+        self
+    }
+
+    pub fn auto_reload(mut self, auto_reload: u16) -> Self {
+        self.auto_reload = auto_reload;
+        self
+    }
+
+    #[inline]
+    fn slave_mode_bits(&self) -> u8 {
+        match self.mode {
+            Mode::Ci0 => 0b001,
+            Mode::Ci1 => 0b010,
+            Mode::Both => 0b011,
+        }
+    }
+}
+
+/// Quadrature encoder on channel 0 and 1 of a timer
+pub struct Qei<TIMER, PINS> {
+    timer: TIMER,
+    pins: PINS,
+    // counter wraps counted by `handle_wrap`, negative for underflows
+    wraps: i32,
+}
+
+macro_rules! qei {
+    ($($TIMERX:ident: (
+        $timerx:ident, $timerxen:ident, $timerxrst:ident, $APBX:ident, $set_remap:expr
+    ),)+) => {
+$(
+impl<P0, P1> Qei<$TIMERX, (P0, P1)> {
+    /// Power on the timer and start counting encoder edges from zero
+    pub fn $timerx(
+        timer: $TIMERX,
+        pins: (P0, P1),
+        pcf0: &mut PCF0,
+        config: Config,
+        apb: &mut $APBX,
+    ) -> Self
+    where
+        P0: Ch0<$TIMERX, Input<Floating>>,
+        P1: Ch1<$TIMERX, Input<Floating>>,
+    {
+        let remap = remap_bits(&[P0::REMAP, P1::REMAP]);
+        riscv::interrupt::free(|_| {
+            apb.en().modify(|_, w| w.$timerxen().set_bit());
+            apb.rst().modify(|_, w| w.$timerxrst().set_bit());
+            apb.rst().modify(|_, w| w.$timerxrst().clear_bit());
+            pcf0.pcf0().modify(|_, w| {
+                ($set_remap)(w, remap);
+                w
+            });
+        });
+        let filter = config.filter;
+        // CI0 and CI1 mapped on their own pins
+        timer.chctl0_input().write(|w| unsafe {
+            w.ch0ms().bits(0b01).ch0capflt().bits(filter)
+                .ch1ms().bits(0b01).ch1capflt().bits(filter)
+        });
+        // inverting one input reverses the counting direction
+        timer.chctl2.write(|w| {
+            w.ch0p().bit(config.invert).ch0en().set_bit()
+                .ch1p().clear_bit().ch1en().set_bit()
+        });
+        timer.smcfg.write(|w| unsafe { w.smc().bits(config.slave_mode_bits()) });
+        timer.car.write(|w| unsafe { w.carl().bits(config.auto_reload) });
+        timer.ctl0.modify(|_, w| w.cen().set_bit());
+        Qei {
+            timer,
+            pins,
+            wraps: 0,
+        }
+    }
+
+    /// Power down the timer and return ownership of owned registers
+    pub fn release(self, apb: &mut $APBX) -> ($TIMERX, (P0, P1)) {
+        self.timer.ctl0.modify(|_, w| w.cen().clear_bit());
+        apb.en().modify(|_, w| w.$timerxen().clear_bit());
+        (self.timer, self.pins)
+    }
+
+    /// Returns the auto-reload value
+    pub fn auto_reload(&self) -> u16 {
+        self.timer.car.read().carl().bits()
+    }
+
+    /// Sets the auto-reload value, after which the counter wraps around
+    pub fn set_auto_reload(&mut self, auto_reload: u16) {
+        self.timer.car.write(|w| unsafe { w.carl().bits(auto_reload) });
+    }
+
+    /// Sets the counter value
+    pub fn set_count(&mut self, count: u16) {
+        self.timer.cnt.write(|w| unsafe { w.cnt().bits(count) });
+    }
+
+    /// Enables the update interrupt, which fires when the counter wraps
+    pub fn listen_wrap(&mut self) {
+        self.timer.dmainten.modify(|_, w| w.upie().set_bit());
+    }
+
+    /// Disables the update interrupt
+    pub fn unlisten_wrap(&mut self) {
+        self.timer.dmainten.modify(|_, w| w.upie().clear_bit());
+    }
+
+    /// Accounts a pending counter wrap for the extended count and clears the
+    /// update interrupt flag; call this in the update interrupt handler.
+    ///
+    /// Returns `true` if a wrap was pending.
+    pub fn handle_wrap(&mut self) -> bool {
+        if self.timer.intf.read().upif().bit_is_clear() {
+            return false;
+        }
+        self.timer.intf.write(|w| unsafe { w.bits(!UPIF) });
+        // DIR may have changed since the wrap; the counter has moved little,
+        // so it is near 0 after an overflow and near CAR after an underflow
+        let count = self.timer.cnt.read().cnt().bits();
+        if count > self.auto_reload() / 2 {
+            self.wraps = self.wraps.wrapping_sub(1);
+        } else {
+            self.wraps = self.wraps.wrapping_add(1);
+        }
+        true
+    }
+
+    /// Returns the count extended to 32 bits by counted wraps.
+    ///
+    /// Wraps are only counted by `handle_wrap`; if the auto-reload value is
+    /// changed, previously counted wraps are scaled by the new value.
+    pub fn count_extended(&mut self) -> i32 {
+        loop {
+            self.handle_wrap();
+            let count = self.timer.cnt.read().cnt().bits();
+            // retry if the counter wrapped while reading
+            if self.timer.intf.read().upif().bit_is_clear() {
+                let period = self.auto_reload() as i32 + 1;
+                return self.wraps.wrapping_mul(period).wrapping_add(count as i32);
+            }
+        }
+    }
+}
+
+impl<PINS> embedded_hal::qei::Qei for Qei<$TIMERX, PINS> {
+    type Error = Infallible;
+    type Count = u16;
+
+    fn try_count(&self) -> Result<u16, Self::Error> {
+        Ok(self.timer.cnt.read().cnt().bits())
+    }
+
+    fn try_direction(&self) -> Result<Direction, Self::Error> {
+        Ok(if self.timer.ctl0.read().dir().bit_is_set() {
+            Direction::Downcounting
+        } else {
+            Direction::Upcounting
+        })
+    }
+}
+)+
+    };
+}
+
+qei! {
+    TIMER0: (timer0, timer0en, timer0rst, APB2,
+        |w: &mut pcf0::W, bits| unsafe { w.timer0_remap().bits(bits); }),
+    TIMER1: (timer1, timer1en, timer1rst, APB1,
+        |w: &mut pcf0::W, bits| unsafe { w.timer1_remap().bits(bits); }),
+    TIMER2: (timer2, timer2en, timer2rst, APB1,
+        |w: &mut pcf0::W, bits| unsafe { w.timer2_remap().bits(bits); }),
+    TIMER3: (timer3, timer3en, timer3rst, APB1,
+        |w: &mut pcf0::W, bits| { w.timer3_remap().bit(bits != 0); }),
+    TIMER4: (timer4, timer4en, timer4rst, APB1,
+        |_: &mut pcf0::W, _| {}),
+}
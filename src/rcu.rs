@@ -148,6 +148,8 @@ pub struct Clocks {
     apb2_shr: u8, // [0, 4] -> [2, 16]
     adc_div: u8,  // {2, 4, 6, 8, 12, 16}
     usb_valid: bool,
+    ck_i2s: Hertz,
+    hxtal: Option<Hertz>,
 }
 
 impl Clocks {
@@ -171,7 +173,7 @@ impl Clocks {
         Hertz(self.ck_sys.0 >> (self.ahb_shr + self.apb2_shr))
     }
 
-    /// Returns the freqency of the CK_TIMERx clock
+    /// Returns the freqency of the CK_TIMERx clock for TIMER0 on APB2
     pub const fn ck_timerx(&self) -> Hertz {
         // Hertz(self.ck_sys.0 >> (self.ahb_shr + self.apb2_shr
         //     - if self.apb2_shr == 0 { 0 } else { 1 }))
@@ -181,6 +183,14 @@ impl Clocks {
         )
     }
 
+    /// Returns the freqency of the CK_TIMERx clock for TIMER1 to TIMER6 on APB1
+    pub const fn ck_timerx_apb1(&self) -> Hertz {
+        Hertz(
+            self.ck_sys.0
+                >> (self.ahb_shr + self.apb1_shr - [0, 1, 1, 1, 1][self.apb1_shr as usize]),
+        )
+    }
+
     /// Returns the freqency of the CK_ADCx clock
     pub const fn ck_adc(&self) -> Hertz {
         Hertz((self.ck_sys.0 >> (self.ahb_shr + self.apb2_shr)) / self.adc_div as u32)
@@ -190,6 +200,17 @@ impl Clocks {
     pub const fn ck_usbfs_valid(&self) -> bool {
         self.usb_valid
     }
+
+    /// Returns the frequency of the CK_I2S clock for I2S1 and I2S2
+    pub const fn ck_i2s(&self) -> Hertz {
+        self.ck_i2s
+    }
+
+    /// Returns the frequency of the HXTAL oscillator, or `None` if it is not
+    /// enabled
+    pub const fn hxtal(&self) -> Option<Hertz> {
+        self.hxtal
+    }
 }
 
 /// Strict clock configurator
@@ -248,8 +269,11 @@ impl Strict {
         self
     }
 
-    #[doc(hidden)] // todo
     /// Sets the desired frequency for the CK_I2S clock
+    ///
+    /// The I2S clock is CK_SYS by default. If another frequency is given, the
+    /// clock is generated by PLL2 (as `2 * CK_PLL2`) from HXTAL, thus HXTAL
+    /// must be used.
     pub fn ck_i2s(mut self, freq: impl Into<Hertz>) -> Self {
         let freq_hz = freq.into().0;
         self.target_ck_i2s = NonZeroU32::new(freq_hz);
@@ -421,6 +445,17 @@ impl Strict {
         } else {
             panic!("invalid freqency")
         };
+        // CK_I2S: CK_SYS, or (HXTAL / PREDV1) * PLL2MF * 2
+        let target_ck_i2s = self.target_ck_i2s.map(|f| f.get());
+        let pll2 = match (target_ck_i2s, self.hxtal) {
+            (None, _) => None,
+            (Some(i2s), _) if i2s == target_ck_sys => None,
+            (Some(i2s), Some(hxtal)) => match calc_pll2(hxtal.get(), i2s) {
+                Some(ans) => Some(ans),
+                None => panic!("invalid frequency"),
+            },
+            (Some(_), None) => panic!("invalid frequency"),
+        };
         // 1. enable IRC8M
         if self.hxtal.is_none() {
             // enable IRC8M
@@ -469,6 +504,20 @@ impl Strict {
             cfg.cfg0()
                 .modify(|_, w| unsafe { w.usbfspsc().bits(usbfspsc) });
         }
+        // 8. configure PLL2 as the I2S clock source if necessary
+        if let Some((predv1, pll2mf)) = pll2 {
+            cfg.cfg1().modify(|_, w| unsafe {
+                w.predv1().bits(predv1);
+                w.pll2mf().bits(pll2mf);
+                w.i2s1sel().set_bit();
+                w.i2s2sel().set_bit()
+            });
+            cfg.ctl().modify(|_, w| w.pll2en().set_bit());
+            while cfg.ctl().read().pll2stb().bit_is_clear() {}
+        } else {
+            cfg.cfg1()
+                .modify(|_, w| w.i2s1sel().clear_bit().i2s2sel().clear_bit());
+        }
         // todo: verify if three switches in one modify is okay
         cfg.cfg0().modify(|_, w| unsafe {
             // 6. adjust AHB and APB clocks
@@ -486,10 +535,36 @@ impl Strict {
             apb2_shr: apb2psc - 0b011,
             adc_div: (target_ck_apb2 / target_ck_adc) as u8,
             usb_valid,
+            ck_i2s: Hertz(target_ck_i2s.unwrap_or(target_ck_sys)),
+            hxtal: self.hxtal.map(|f| Hertz(f.get())),
         }
     }
 }
 
+// Find (PREDV1, PLL2MF) register bits to get `2 * HXTAL / PREDV1 * PLL2MF == ck_i2s`
+fn calc_pll2(hxtal: u32, ck_i2s: u32) -> Option<(u8, u8)> {
+    // PLL2MF: 0110 => 8, 0111 => 9, ..., 1100 => 14; 1110 => 16, 1111 => 20
+    const PLL2MF: [(u32, u8); 9] = [
+        (8, 0b0110),
+        (9, 0b0111),
+        (10, 0b1000),
+        (11, 0b1001),
+        (12, 0b1010),
+        (13, 0b1011),
+        (14, 0b1100),
+        (16, 0b1110),
+        (20, 0b1111),
+    ];
+    for div in 1..=16 {
+        for &(mul, pll2mf) in PLL2MF.iter() {
+            if hxtal as u64 * mul as u64 * 2 == ck_i2s as u64 * div as u64 {
+                return Some(((div - 1) as u8, pll2mf));
+            }
+        }
+    }
+    None
+}
+
 /// (TODO) Precise clock configurator
 ///
 /// This configurator would offer config to get the most precise output value possible
@@ -511,3 +586,41 @@ impl BDCTL {
         unsafe { &(*RCU::ptr()).bdctl }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // CK_I2S given by the PLL2 register bits found
+    fn ck_pll2(hxtal: u32, (predv1, pll2mf): (u8, u8)) -> u32 {
+        let mul = match pll2mf {
+            0b1110 => 16,
+            0b1111 => 20,
+            bits => bits as u32 + 2,
+        };
+        2 * hxtal / (predv1 as u32 + 1) * mul
+    }
+
+    #[test]
+    fn pll2_reaches_ck_i2s() {
+        for &(hxtal, ck_i2s) in [
+            (8_000_000, 96_000_000),
+            (8_000_000, 64_000_000),
+            (8_000_000, 160_000_000),
+            (25_000_000, 100_000_000),
+            (25_000_000, 80_000_000),
+        ]
+        .iter()
+        {
+            let bits = calc_pll2(hxtal, ck_i2s).unwrap();
+            assert_eq!(ck_pll2(hxtal, bits), ck_i2s);
+        }
+    }
+
+    #[test]
+    fn pll2_unreachable() {
+        // 2 * 8 MHz * 20 is the highest
+        assert_eq!(calc_pll2(8_000_000, 400_000_000), None);
+        assert_eq!(calc_pll2(8_000_000, 86_000_000), None);
+    }
+}
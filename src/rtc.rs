@@ -0,0 +1,293 @@
+//! Real-Time Clock (RTC)
+//!
+//! The RTC is a 32-bit counter in the backup domain, counting at the RTC
+//! clock divided by a 20-bit prescaler. It keeps counting in standby mode,
+//! and while the chip is reset if the backup domain is powered.
+//!
+//! Write access to the backup domain should be enabled before using the RTC,
+//! by splitting the `BKP` peripheral in the `backup` module; `Rtc::new`
+//! takes its `WriteAccess` part as a proof.
+//!
+//! Second, alarm and overflow interrupts call `RTC_IRQHandler`; the alarm
+//! could also wake the chip through EXTI line 17 and `RTC_Alarm_IRQHandler`.
+//!
+//! Ref: Section 14, the User Manual
+use crate::backup::WriteAccess;
+use crate::pac::{rtc, RCU, RTC};
+use crate::rcu::{Clocks, BDCTL};
+use crate::unit::Hertz;
+
+/// RTC clock source
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ClockSource {
+    /// 32.768 KHz low speed crystal oscillator, LXTAL
+    Lxtal,
+    /// About 40 KHz internal RC oscillator, IRC40K
+    Irc40k,
+    /// High speed crystal oscillator divided by 128; HXTAL is enabled by the
+    /// clock configuration, and the RTC stops when the core power domain is off
+    HxtalDiv128,
+}
+
+impl ClockSource {
+    /// Nominal frequency of the RTC clock, or `None` if HXTAL is selected
+    /// but not enabled in `clocks`
+    pub fn frequency(self, clocks: &Clocks) -> Option<Hertz> {
+        match self {
+            ClockSource::Lxtal => Some(Hertz(32_768)),
+            ClockSource::Irc40k => Some(Hertz(40_000)),
+            ClockSource::HxtalDiv128 => clocks.hxtal().map(|hxtal| Hertz(hxtal.0 / 128)),
+        }
+    }
+
+    #[inline]
+    fn bits(self) -> u8 {
+        match self {
+            ClockSource::Lxtal => 0b01,
+            ClockSource::Irc40k => 0b10,
+            ClockSource::HxtalDiv128 => 0b11,
+        }
+    }
+}
+
+/// RTC interrupt events
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// Every tick of the counter
+    Second,
+    /// The counter reaches the alarm value
+    Alarm,
+    /// The counter overflows
+    Overflow,
+}
+
+/// RTC errors
+#[derive(Debug)]
+pub enum Error {
+    /// LXTAL did not get stable in time, e.g. no crystal is fitted
+    LxtalTimeout,
+    /// IRC40K did not get stable in time
+    Irc40kTimeout,
+    /// HXTAL is selected but not enabled by the clock configuration
+    HxtalDisabled,
+    /// The RTC registers were not synchronized in time, i.e. the RTC clock
+    /// does not run
+    SyncTimeout,
+}
+
+// Number of LXTALSTB polls before giving up; a 32.768 KHz crystal takes up
+// to a few seconds to start, and each poll takes several clock cycles.
+const LXTAL_TIMEOUT: u32 = 50_000_000;
+// Number of IRC40KSTB polls before giving up; IRC40K starts within tens of
+// microseconds.
+const IRC40K_TIMEOUT: u32 = 1_000_000;
+// Number of RSYNF polls before giving up; synchronization takes a few RTC
+// clock cycles, which are a few hundred microseconds at 32.768 KHz.
+const SYNC_TIMEOUT: u32 = 5_000_000;
+
+// Flags in CTL are cleared by writing 0 and unchanged by writing 1, so the
+// other flags are written as 1 to keep those set in the meantime.
+const SCIF: u32 = 1 << 0;
+const ALRMIF: u32 = 1 << 1;
+const OVIF: u32 = 1 << 2;
+const RSYNF: u32 = 1 << 3;
+const FLAGS: u32 = SCIF | ALRMIF | OVIF | RSYNF;
+// configuration mode flag
+const CMF: u32 = 1 << 4;
+
+/// Real-time clock
+pub struct Rtc {
+    rtc: RTC,
+}
+
+impl Rtc {
+    /// Enables the RTC clock source and configures the RTC to tick once
+    /// per second.
+    ///
+    /// If the RTC already runs from the same clock source, e.g. after a
+    /// reset of the chip, the counter value is kept. Changing the clock
+    /// source resets the backup domain, including backup data registers.
+    ///
+    /// If the clock source does not get stable, or HXTAL is selected but
+    /// not enabled in `clocks`, the RTC is returned with the error, so that
+    /// another clock source could be used. An oscillator enabled here is
+    /// disabled again on timeout.
+    pub fn new(
+        rtc: RTC,
+        source: ClockSource,
+        clocks: &Clocks,
+        bdctl: &mut BDCTL,
+        _write_access: &mut WriteAccess,
+    ) -> Result<Self, (Error, RTC)> {
+        let frequency = match source.frequency(clocks) {
+            Some(frequency) => frequency,
+            None => return Err((Error::HxtalDisabled, rtc)),
+        };
+        let bdctl = bdctl.bdctl();
+        let current = bdctl.read();
+        let running = current.rtcen().bit_is_set() && current.rtcsrc().bits() == source.bits();
+        if !running {
+            if current.rtcsrc().bits() != 0 {
+                // clock source could only be changed after a backup domain reset
+                bdctl.modify(|_, w| w.bkprst().set_bit());
+                bdctl.modify(|_, w| w.bkprst().clear_bit());
+            }
+            match source {
+                ClockSource::Lxtal => {
+                    bdctl.modify(|_, w| w.lxtalen().set_bit());
+                    let mut polls = 0;
+                    while bdctl.read().lxtalstb().bit_is_clear() {
+                        polls += 1;
+                        if polls >= LXTAL_TIMEOUT {
+                            bdctl.modify(|_, w| w.lxtalen().clear_bit());
+                            return Err((Error::LxtalTimeout, rtc));
+                        }
+                    }
+                }
+                ClockSource::Irc40k => {
+                    // RSTSCK is not constrained by other modules
+                    let rstsck = unsafe { &(*RCU::ptr()).rstsck };
+                    riscv::interrupt::free(|_| {
+                        rstsck.modify(|_, w| w.irc40ken().set_bit());
+                    });
+                    let mut polls = 0;
+                    while rstsck.read().irc40kstb().bit_is_clear() {
+                        polls += 1;
+                        if polls >= IRC40K_TIMEOUT {
+                            riscv::interrupt::free(|_| {
+                                rstsck.modify(|_, w| w.irc40ken().clear_bit());
+                            });
+                            return Err((Error::Irc40kTimeout, rtc));
+                        }
+                    }
+                }
+                // HXTAL is enabled by the clock configuration
+                ClockSource::HxtalDiv128 => {}
+            }
+            bdctl.modify(|_, w| unsafe { w.rtcsrc().bits(source.bits()).rtcen().set_bit() });
+        }
+        let mut rtc = Rtc { rtc };
+        if let Err(e) = rtc.wait_sync() {
+            return Err((e, rtc.rtc));
+        }
+        rtc.set_prescaler(frequency.0 - 1);
+        Ok(rtc)
+    }
+
+    /// Releases the RTC; the RTC keeps counting
+    pub fn release(self) -> RTC {
+        self.rtc
+    }
+
+    /// Sets the prescaler; the counter ticks at the RTC clock divided by
+    /// `prescaler + 1`
+    pub fn set_prescaler(&mut self, prescaler: u32) {
+        assert!(prescaler < 1 << 20);
+        self.configure(|rtc| {
+            rtc.psch
+                .write(|w| unsafe { w.psc().bits((prescaler >> 16) as u8) });
+            rtc.pscl
+                .write(|w| unsafe { w.psc().bits(prescaler as u16) });
+        });
+    }
+
+    /// Reads the counter value
+    pub fn counter(&self) -> u32 {
+        // read the high half again in case the low half carried
+        loop {
+            let hi = self.rtc.cnth.read().cnt().bits();
+            let lo = self.rtc.cntl.read().cnt().bits();
+            if hi == self.rtc.cnth.read().cnt().bits() {
+                return (hi as u32) << 16 | lo as u32;
+            }
+        }
+    }
+
+    /// Sets the counter value
+    pub fn set_counter(&mut self, value: u32) {
+        self.configure(|rtc| {
+            rtc.cnth
+                .write(|w| unsafe { w.cnt().bits((value >> 16) as u16) });
+            rtc.cntl.write(|w| unsafe { w.cnt().bits(value as u16) });
+        });
+    }
+
+    /// Sets the alarm value; the alarm flag is set when the counter reaches
+    /// this value
+    pub fn set_alarm(&mut self, value: u32) {
+        self.configure(|rtc| {
+            rtc.alrmh
+                .write(|w| unsafe { w.alrm().bits((value >> 16) as u16) });
+            rtc.alrml.write(|w| unsafe { w.alrm().bits(value as u16) });
+        });
+    }
+
+    /// Enables the interrupt of the event
+    pub fn listen(&mut self, event: Event) {
+        self.wait_write();
+        self.rtc.inten.modify(|_, w| match event {
+            Event::Second => w.scie().set_bit(),
+            Event::Alarm => w.alrmie().set_bit(),
+            Event::Overflow => w.ovie().set_bit(),
+        });
+    }
+
+    /// Disables the interrupt of the event
+    pub fn unlisten(&mut self, event: Event) {
+        self.wait_write();
+        self.rtc.inten.modify(|_, w| match event {
+            Event::Second => w.scie().clear_bit(),
+            Event::Alarm => w.alrmie().clear_bit(),
+            Event::Overflow => w.ovie().clear_bit(),
+        });
+    }
+
+    /// Checks if the flag of the event is set
+    pub fn is_pending(&self, event: Event) -> bool {
+        let ctl = self.rtc.ctl.read();
+        match event {
+            Event::Second => ctl.scif().bit_is_set(),
+            Event::Alarm => ctl.alrmif().bit_is_set(),
+            Event::Overflow => ctl.ovif().bit_is_set(),
+        }
+    }
+
+    /// Clears the flag of the event; call this in the interrupt handler
+    pub fn clear_flag(&mut self, event: Event) {
+        let flag = match event {
+            Event::Second => SCIF,
+            Event::Alarm => ALRMIF,
+            Event::Overflow => OVIF,
+        };
+        self.wait_write();
+        self.rtc.ctl.write(|w| unsafe { w.bits(FLAGS & !flag) });
+    }
+
+    // Registers read through APB are synchronized after the APB1 interface
+    // is reset or its clock is stopped; wait for RSYNF before reading.
+    fn wait_sync(&mut self) -> Result<(), Error> {
+        self.rtc.ctl.write(|w| unsafe { w.bits(FLAGS & !RSYNF) });
+        for _ in 0..SYNC_TIMEOUT {
+            if self.rtc.ctl.read().rsynf().bit_is_set() {
+                return Ok(());
+            }
+        }
+        Err(Error::SyncTimeout)
+    }
+
+    // The last write operation should finish before the next one.
+    #[inline]
+    fn wait_write(&self) {
+        while self.rtc.ctl.read().lwoff().bit_is_clear() {}
+    }
+
+    // PSC, CNT and ALRM could only be written in configuration mode; the
+    // writes take effect after leaving it.
+    fn configure<F: FnOnce(&rtc::RegisterBlock)>(&mut self, f: F) {
+        self.wait_write();
+        self.rtc.ctl.write(|w| unsafe { w.bits(FLAGS | CMF) });
+        f(&self.rtc);
+        self.rtc.ctl.write(|w| unsafe { w.bits(FLAGS) });
+        self.wait_write();
+    }
+}
@@ -1,11 +1,59 @@
 //! Serial Peripheral Interface (SPI) bus
+//!
+//! Besides the common full duplex mode, this module supports the bidirectional
+//! (3-wire, half duplex) mode where data travels on one single MOSI line, and the
+//! receive only mode where the MOSI pin is not used. Operation mode is encoded
+//! as a type state of the `Spi` struct, and pins required differ by modes:
+//!
+//! | Mode                  | Constructor          | Pins                     |
+//! |:----------------------|:---------------------|:-------------------------|
+//! | [`FullDuplexMode`]    | `spiX`               | `(SCK, MISO, MOSI, NSS)` |
+//! | [`BidirectionalMode`] | `spiX_bidirectional` | `(SCK, MOSI, NSS)`       |
+//! | [`ReceiveOnlyMode`]   | `spiX_receive_only`  | `(SCK, MISO, NSS)`       |
+//!
+//! [`FullDuplexMode`]: struct.FullDuplexMode.html
+//! [`BidirectionalMode`]: struct.BidirectionalMode.html
+//! [`ReceiveOnlyMode`]: struct.ReceiveOnlyMode.html
+//!
+//! # Slave select (NSS)
+//!
+//! The NSS pin type decides how the slave select signal is managed:
+//!
+//! - If the NSS pin is in `Alternate<PushPull>` mode, the hardware NSS output is
+//!   used (`NSSDRV` set). The NSS line is driven low by hardware as long as the
+//!   SPI is enabled; this is suitable for buses with one single device.
+//! - If the NSS pin is in `Output<PushPull>` mode or [`NoNss`] is given, the
+//!   software NSS mode is used (`SWNSSEN` set). The NSS pin can be handed back
+//!   using [`split_nss`] and driven by application, or be wrapped together with
+//!   the SPI into a [`SpiDevice`] which asserts it around every blocking transfer.
+//!
+//! [`NoNss`]: struct.NoNss.html
+//! [`split_nss`]: struct.Spi.html#method.split_nss
+//! [`SpiDevice`]: struct.SpiDevice.html
+//!
+//! # DMA transfers
+//!
+//! Bulk transfers could be offloaded to DMA using `with_tx_dma`, `with_rx_dma`
+//! or `with_dma`. Transmission is available in full duplex and bidirectional
+//! modes, and `with_dma` only in full duplex mode. Each SPI uses fixed DMA
+//! channels:
+//!
+//! | SPI  | RX channel | TX channel |
+//! |:-----|:-----------|:-----------|
+//! | SPI0 | DMA0 CH1   | DMA0 CH2   |
+//! | SPI1 | DMA0 CH3   | DMA0 CH4   |
+//! | SPI2 | DMA1 CH0   | DMA1 CH1   |
+use crate::dma::{self, setup_channel, Channel, Direction, TransferPayload};
 use crate::gpio::gpioa::*;
 use crate::gpio::gpiob::*;
 use crate::gpio::{Alternate, Floating, Input, Output, PushPull};
 use crate::pac::{SPI0, SPI1, SPI2};
 use crate::rcu::{Clocks, APB1, APB2};
 use crate::unit::Hertz;
+use core::convert::Infallible;
+use core::marker::PhantomData;
 use embedded_hal::blocking::spi::*;
+use embedded_hal::digital::OutputPin;
 use embedded_hal::spi::{FullDuplex, Mode, Phase, Polarity};
 
 /// SPI error
@@ -22,20 +70,91 @@ pub enum Error {
 }
 
 /// SPI object that can be used to make FullDuplex SPI peripherals
-pub struct Spi<SPI, PINS> {
+pub struct Spi<SPI, PINS, MODE = FullDuplexMode> {
     spi: SPI,
     pins: PINS,
+    // prescaler bits, used to wait for SCK periods when stopping reception
+    psc: u8,
+    _mode: PhantomData<MODE>,
 }
 
+/// Full duplex mode (type state)
+///
+/// Both MISO and MOSI lines are used; data is sent and received at the same time.
+pub struct FullDuplexMode;
+
+/// Bidirectional mode (type state)
+///
+/// Data is transferred through one single line (the MOSI pin for master) and
+/// the direction is switched by the `BDOEN` bit. This is also known as
+/// 3-wire or half duplex SPI mode.
+pub struct BidirectionalMode;
+
+/// Receive only mode (type state)
+///
+/// Only the MISO line is used; clock is generated whenever the SPI is enabled.
+pub struct ReceiveOnlyMode;
+
 #[doc(hidden)]
 mod private {
     pub trait Sealed {}
 }
 
+/// Marker trait for valid SPI operation modes
+pub trait OperationMode: private::Sealed {
+    // (BDEN, RO) bits this mode is represented into
+    #[doc(hidden)]
+    const BDEN_RO: (bool, bool);
+}
+
+impl private::Sealed for FullDuplexMode {}
+impl OperationMode for FullDuplexMode {
+    const BDEN_RO: (bool, bool) = (false, false);
+}
+
+impl private::Sealed for BidirectionalMode {}
+impl OperationMode for BidirectionalMode {
+    const BDEN_RO: (bool, bool) = (true, false);
+}
+
+impl private::Sealed for ReceiveOnlyMode {}
+impl OperationMode for ReceiveOnlyMode {
+    const BDEN_RO: (bool, bool) = (false, true);
+}
+
+// PSC[2:0] bits for the nearest frequency not faster than `freq`
+#[inline]
+fn prescaler_bits(pclk: Hertz, freq: Hertz) -> u8 {
+    match pclk.0 / freq.0 {
+        0 => unreachable!(),
+        2..=2 => 0b000,
+        4..=5 => 0b001,
+        8..=11 => 0b010,
+        16..=23 => 0b011,
+        32..=39 => 0b100,
+        64..=95 => 0b101,
+        128..=191 => 0b110,
+        _ => 0b111,
+    }
+}
+
 pub trait SckPin<SPI>: private::Sealed {}
 pub trait MisoPin<SPI>: private::Sealed {}
 pub trait MosiPin<SPI>: private::Sealed {}
-pub trait NssPin<SPI>: private::Sealed {}
+pub trait NssPin<SPI>: private::Sealed {
+    // If hardware NSS output should be used for this pin
+    #[doc(hidden)]
+    const HARDWARE: bool;
+}
+
+/// Placeholder for the NSS pin when the slave select signal is not managed by
+/// the SPI peripheral (software NSS mode)
+pub struct NoNss;
+
+impl private::Sealed for NoNss {}
+impl<SPI> NssPin<SPI> for NoNss {
+    const HARDWARE: bool = false;
+}
 
 macro_rules! pins {
     ($spi:ident, SCK: [$($sck:ident),*], MISO: [$($miso:ident),*], MOSI: [$($mosi:ident),*], NSS: [$($nss:ident),*]) => {
@@ -53,18 +172,23 @@ macro_rules! pins {
         )*
         $(
             impl private::Sealed for $nss<Alternate<PushPull>> {}
-            impl NssPin<$spi> for $nss<Alternate<PushPull>> {}
+            impl NssPin<$spi> for $nss<Alternate<PushPull>> {
+                const HARDWARE: bool = true;
+            }
             impl private::Sealed for $nss<Output<PushPull>> {}
-            impl NssPin<$spi> for $nss<Output<PushPull>> {}
+            impl NssPin<$spi> for $nss<Output<PushPull>> {
+                const HARDWARE: bool = false;
+            }
         )*
     }
 
 }
 
 macro_rules! spi {
-    ($($SPIX:ident: ($spiX:ident, $APBX:ident, $spiXen:ident, $spiXrst:ident, $pclkX:ident),)+) => {
+    ($($SPIX:ident: ($spiX:ident, $spiX_bidirectional:ident, $spiX_receive_only:ident,
+        $APBX:ident, $spiXen:ident, $spiXrst:ident, $pclkX:ident),)+) => {
         $(
-            impl<SCK, MISO, MOSI, NSS> Spi<$SPIX, (SCK, MISO, MOSI, NSS)> {
+            impl<SCK, MISO, MOSI, NSS> Spi<$SPIX, (SCK, MISO, MOSI, NSS), FullDuplexMode> {
                 /// Configures the SPI peripheral to operate in full duplex master mode
                 pub fn $spiX<F>(
                     spi: $SPIX,
@@ -81,53 +205,180 @@ macro_rules! spi {
                     MOSI: MosiPin<$SPIX>,
                     NSS: NssPin<$SPIX>
                 {
+                    Self::configure(spi, pins, NSS::HARDWARE, mode, freq.into(), clocks, apb)
+                }
+            }
 
-                    let prescaler_bits = match clocks.$pclkX().0 / freq.into().0 {
-                        0 => unreachable!(),
-                        2..=2 => 0b000,
-                        4..=5 => 0b001,
-                        8..=11 => 0b010,
-                        16..=23 => 0b011,
-                        32..=39 => 0b100,
-                        64..=95 => 0b101,
-                        128..=191 => 0b110,
-                        _ => 0b111,
-                    };
+            impl<SCK, MOSI, NSS> Spi<$SPIX, (SCK, MOSI, NSS), BidirectionalMode> {
+                /// Configures the SPI peripheral to operate in bidirectional (3-wire)
+                /// master mode.
+                ///
+                /// Data is transferred on the MOSI pin; no MISO pin is needed.
+                pub fn $spiX_bidirectional<F>(
+                    spi: $SPIX,
+                    pins: (SCK, MOSI, NSS),
+                    mode: Mode,
+                    freq: F,
+                    clocks: Clocks,
+                    apb: &mut $APBX,
+                ) -> Self
+                where
+                    F: Into<Hertz>,
+                    SCK: SckPin<$SPIX>,
+                    MOSI: MosiPin<$SPIX>,
+                    NSS: NssPin<$SPIX>
+                {
+                    Self::configure(spi, pins, NSS::HARDWARE, mode, freq.into(), clocks, apb)
+                }
+            }
+
+            impl<SCK, MISO, NSS> Spi<$SPIX, (SCK, MISO, NSS), ReceiveOnlyMode> {
+                /// Configures the SPI peripheral to operate in receive only master mode.
+                ///
+                /// No MOSI pin is needed in this mode.
+                pub fn $spiX_receive_only<F>(
+                    spi: $SPIX,
+                    pins: (SCK, MISO, NSS),
+                    mode: Mode,
+                    freq: F,
+                    clocks: Clocks,
+                    apb: &mut $APBX,
+                ) -> Self
+                where
+                    F: Into<Hertz>,
+                    SCK: SckPin<$SPIX>,
+                    MISO: MisoPin<$SPIX>,
+                    NSS: NssPin<$SPIX>
+                {
+                    Self::configure(spi, pins, NSS::HARDWARE, mode, freq.into(), clocks, apb)
+                }
+            }
+
+            impl<PINS, MODE: OperationMode> Spi<$SPIX, PINS, MODE> {
+                fn configure(
+                    spi: $SPIX,
+                    pins: PINS,
+                    hardware_nss: bool,
+                    mode: Mode,
+                    freq: Hertz,
+                    clocks: Clocks,
+                    apb: &mut $APBX,
+                ) -> Self {
+                    let prescaler_bits = prescaler_bits(clocks.$pclkX(), freq);
+                    let (bden, ro) = MODE::BDEN_RO;
 
                     apb.en().modify(|_,w| w.$spiXen().set_bit());
                     //apb.rst().write(|w| w.$spiXrst().set_bit());
                     //apb.rst().write(|w| w.$spiXrst().clear_bit());
 
                     spi.ctl0.write(|w| w.spien().clear_bit()); //disable while configuring...
-                    spi.ctl1.modify(|_,w| w.nssdrv().clear_bit()); //let application drive the nss pin.
+                    // in hardware NSS mode the NSS pin is driven low while SPI is enabled;
+                    // otherwise let application drive the nss pin.
+                    spi.ctl1.modify(|_,w| w.nssdrv().bit(hardware_nss));
                     unsafe { //unsafe because of call to psc().bits(...)
                         spi.ctl0.modify(|_,w| {
                             w
-                                .bden().clear_bit() //bidirectional
+                                .bden().bit(bden) //bidirectional
+                                .bdoen().bit(bden) //transmit by default in bidirectional mode
                                 .ff16().clear_bit() // 8 bit word size
-                                .ro().clear_bit() //not read-only
+                                .ro().bit(ro) //receive only
                                 .psc().bits(prescaler_bits)
-                                .swnssen().clear_bit() // use hardware nss mode. ??
-                                .swnss().clear_bit()
+                                .swnssen().bit(!hardware_nss) // software nss mode
+                                .swnss().bit(!hardware_nss) // keep internal nss high in master mode
                                 .lf().clear_bit() //MSB first
                                 .mstmod().set_bit() //master mode
                                 .ckpl().bit(mode.polarity == Polarity::IdleHigh)
                                 .ckph().bit(mode.phase == Phase::CaptureOnSecondTransition)
-                                .spien().set_bit()
                         });
                     }
+                    // In master receive only mode, the clock starts as soon as the
+                    // SPI is enabled; it's enabled later when reading.
+                    if !ro {
+                        spi.ctl0.modify(|_,w| w.spien().set_bit());
+                    }
 
-
-                    Spi { spi, pins }
+                    Spi { spi, pins, psc: prescaler_bits, _mode: PhantomData }
                 }
 
                 /// Releases the SPI peripheral and associated pins
-                pub fn free(self) -> ($SPIX, (SCK, MISO, MOSI, NSS)) {
+                pub fn free(self) -> ($SPIX, PINS) {
                     (self.spi, self.pins)
                 }
+
+                // Switch into software NSS mode, keep internal NSS high for master mode
+                #[inline]
+                fn software_nss(&mut self) {
+                    self.spi.ctl1.modify(|_, w| w.nssdrv().clear_bit());
+                    self.spi.ctl0.modify(|_, w| w.swnssen().set_bit().swnss().set_bit());
+                }
+
+                #[inline]
+                fn check_errors(&self) -> Result<(), Error> {
+                    let stat = self.spi.stat.read();
+                    if stat.rxorerr().bit_is_set() {
+                        // clear the flag by reading DATA then STAT
+                        let _ = self.spi.data.read();
+                        let _ = self.spi.stat.read();
+                        Err(Error::ReceiveOverrun)
+                    } else if stat.conferr().bit_is_set() {
+                        Err(Error::ConfigFault)
+                    } else if stat.crcerr().bit_is_set() {
+                        Err(Error::Crc)
+                    } else {
+                        Ok(())
+                    }
+                }
+
+                // Receive words while the clock is generated by hardware.
+                //
+                // In bidirectional receive and receive only master modes, the clock
+                // keeps running until the SPI is disabled. To stop exactly after
+                // the last frame, the Manual suggests to wait for RBNE of the
+                // second-to-last frame, wait for one SCK period and then disable the
+                // SPI; the last frame is still received after SPIEN is cleared.
+                fn receive_words(&mut self, words: &mut [u8]) -> Result<(), Error> {
+                    let len = words.len();
+                    for (i, word) in words.iter_mut().enumerate() {
+                        if i + 1 == len {
+                            // one SCK period lasts 2 << PSC APB cycles, and one
+                            // loop lasts at least one CPU cycle
+                            for _ in 0..(2u32 << self.psc) {
+                                core::hint::spin_loop();
+                            }
+                            self.spi.ctl0.modify(|_, w| w.spien().clear_bit());
+                        }
+                        while self.spi.stat.read().rbne().bit_is_clear() {
+                            self.check_errors()?;
+                        }
+                        *word = self.spi.data.read().spi_data().bits() as u8;
+                    }
+                    Ok(())
+                }
+            }
+
+            impl<SCK, MISO, MOSI, NSS> Spi<$SPIX, (SCK, MISO, MOSI, NSS), FullDuplexMode> {
+                /// Switches into software NSS mode and hands back the NSS pin.
+                ///
+                /// The returned pin is no longer managed by the SPI peripheral.
+                pub fn split_nss(mut self) -> (Spi<$SPIX, (SCK, MISO, MOSI, NoNss), FullDuplexMode>, NSS) {
+                    self.software_nss();
+                    let Spi { spi, pins: (sck, miso, mosi, nss), psc, .. } = self;
+                    (Spi { spi, pins: (sck, miso, mosi, NoNss), psc, _mode: PhantomData }, nss)
+                }
             }
 
-            impl<PINS> FullDuplex<u8> for Spi<$SPIX, PINS> {
+            impl<SCK, PIN, NSS, MODE: OperationMode> Spi<$SPIX, (SCK, PIN, NSS), MODE> {
+                /// Switches into software NSS mode and hands back the NSS pin.
+                ///
+                /// The returned pin is no longer managed by the SPI peripheral.
+                pub fn split_nss(mut self) -> (Spi<$SPIX, (SCK, PIN, NoNss), MODE>, NSS) {
+                    self.software_nss();
+                    let Spi { spi, pins: (sck, pin, nss), psc, .. } = self;
+                    (Spi { spi, pins: (sck, pin, NoNss), psc, _mode: PhantomData }, nss)
+                }
+            }
+
+            impl<PINS> FullDuplex<u8> for Spi<$SPIX, PINS, FullDuplexMode> {
                 type Error = Error;
 
                 fn try_read(&mut self) -> nb::Result<u8, Error> {
@@ -153,8 +404,65 @@ macro_rules! spi {
                 }
             }
 
-            impl<PINS> transfer::Default<u8> for Spi<$SPIX, PINS> {}
-            impl<PINS> write::Default<u8> for Spi<$SPIX, PINS> {}
+            impl<PINS> transfer::Default<u8> for Spi<$SPIX, PINS, FullDuplexMode> {}
+            impl<PINS> write::Default<u8> for Spi<$SPIX, PINS, FullDuplexMode> {}
+
+            impl<PINS> Spi<$SPIX, PINS, BidirectionalMode> {
+                /// Receive words from the bidirectional data line.
+                ///
+                /// The data line is switched into input during reception, and is
+                /// switched back to output after all words are received.
+                pub fn read(&mut self, words: &mut [u8]) -> Result<(), Error> {
+                    if words.is_empty() {
+                        return Ok(());
+                    }
+                    // wait until last transmission ends before turning around
+                    while self.spi.stat.read().tbe().bit_is_clear() {}
+                    while self.spi.stat.read().trans().bit_is_set() {}
+                    // clearing BDOEN starts the clock in master mode
+                    self.spi.ctl0.modify(|_, w| w.bdoen().clear_bit());
+                    let ans = self.receive_words(words);
+                    // switch back to output and re-enable the peripheral
+                    self.spi.ctl0.modify(|_, w| w.spien().clear_bit().bdoen().set_bit());
+                    self.spi.ctl0.modify(|_, w| w.spien().set_bit());
+                    ans
+                }
+            }
+
+            impl<PINS> Write<u8> for Spi<$SPIX, PINS, BidirectionalMode> {
+                type Error = Error;
+
+                fn try_write(&mut self, words: &[u8]) -> Result<(), Error> {
+                    for &word in words {
+                        while self.spi.stat.read().tbe().bit_is_clear() {
+                            self.check_errors()?;
+                        }
+                        self.spi.data.write(|w| unsafe { w.spi_data().bits(word.into()) });
+                    }
+                    // wait for the last frame to be shifted out
+                    while self.spi.stat.read().tbe().bit_is_clear() {}
+                    while self.spi.stat.read().trans().bit_is_set() {}
+                    Ok(())
+                }
+            }
+
+            impl<PINS> Spi<$SPIX, PINS, ReceiveOnlyMode> {
+                /// Receive words from the MISO line.
+                ///
+                /// The clock is generated only during this function.
+                pub fn read(&mut self, words: &mut [u8]) -> Result<(), Error> {
+                    if words.is_empty() {
+                        return Ok(());
+                    }
+                    // drop stale data, then enabling starts the clock in master mode
+                    let _ = self.spi.data.read();
+                    self.spi.ctl0.modify(|_, w| w.spien().set_bit());
+                    let ans = self.receive_words(words);
+                    // on errors SPIEN is still set, which keeps the clock running
+                    self.spi.ctl0.modify(|_, w| w.spien().clear_bit());
+                    ans
+                }
+            }
         )+
     }
 }
@@ -181,7 +489,367 @@ pins! {SPI2,
 }
 
 spi! {
-    SPI0: (spi0, APB2, spi0en, spi0rst, ck_apb2),
-    SPI1: (spi1, APB1, spi1en, spi1rst, ck_apb1),
-    SPI2: (spi2, APB1, spi2en, spi2rst, ck_apb1),
+    SPI0: (spi0, spi0_bidirectional, spi0_receive_only, APB2, spi0en, spi0rst, ck_apb2),
+    SPI1: (spi1, spi1_bidirectional, spi1_receive_only, APB1, spi1en, spi1rst, ck_apb1),
+    SPI2: (spi2, spi2_bidirectional, spi2_receive_only, APB1, spi2en, spi2rst, ck_apb1),
+}
+
+/// SPI device with a dedicated chip select pin
+///
+/// The chip select pin is asserted (driven low) before each blocking transfer
+/// or write operation, and is deasserted after the operation ends. This allows
+/// multiple devices to share one SPI bus in software NSS mode.
+pub struct SpiDevice<SPI, PINS, CS, MODE = FullDuplexMode> {
+    spi: Spi<SPI, PINS, MODE>,
+    cs: CS,
+}
+
+impl<SPI, PINS, CS, MODE> SpiDevice<SPI, PINS, CS, MODE>
+where
+    CS: OutputPin<Error = Infallible>,
+{
+    /// Wraps the SPI bus and the chip select pin into a device.
+    ///
+    /// The chip select pin is set high (inactive) at once.
+    pub fn new(spi: Spi<SPI, PINS, MODE>, mut cs: CS) -> Self {
+        cs.try_set_high().ok();
+        SpiDevice { spi, cs }
+    }
+
+    /// Releases the SPI bus and the chip select pin
+    pub fn release(self) -> (Spi<SPI, PINS, MODE>, CS) {
+        (self.spi, self.cs)
+    }
+
+    #[inline]
+    fn selected<T>(&mut self, f: impl FnOnce(&mut Spi<SPI, PINS, MODE>) -> T) -> T {
+        self.cs.try_set_low().ok();
+        let ans = f(&mut self.spi);
+        self.cs.try_set_high().ok();
+        ans
+    }
+}
+
+impl<SPI, PINS, CS, MODE> Transfer<u8> for SpiDevice<SPI, PINS, CS, MODE>
+where
+    Spi<SPI, PINS, MODE>: Transfer<u8, Error = Error>,
+    CS: OutputPin<Error = Infallible>,
+{
+    type Error = Error;
+
+    fn try_transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Error> {
+        self.selected(move |spi| spi.try_transfer(words))
+    }
+}
+
+impl<SPI, PINS, CS, MODE> Write<u8> for SpiDevice<SPI, PINS, CS, MODE>
+where
+    Spi<SPI, PINS, MODE>: Write<u8, Error = Error>,
+    CS: OutputPin<Error = Infallible>,
+{
+    type Error = Error;
+
+    fn try_write(&mut self, words: &[u8]) -> Result<(), Error> {
+        self.selected(|spi| spi.try_write(words))
+    }
+}
+
+/// SPI with a DMA channel for transmission
+pub struct SpiTxDma<SPI, PINS, MODE, CH> {
+    spi: Spi<SPI, PINS, MODE>,
+    channel: CH,
+}
+
+/// SPI with a DMA channel for reception
+pub struct SpiRxDma<SPI, PINS, MODE, CH> {
+    spi: Spi<SPI, PINS, MODE>,
+    channel: CH,
+}
+
+/// SPI with DMA channels for both reception and transmission
+pub struct SpiDma<SPI, PINS, MODE, RXCH, TXCH> {
+    spi: Spi<SPI, PINS, MODE>,
+    rx_channel: RXCH,
+    tx_channel: TXCH,
+}
+
+impl<SPI, PINS, MODE, CH> SpiTxDma<SPI, PINS, MODE, CH> {
+    /// Releases the SPI and the DMA channel
+    pub fn release(self) -> (Spi<SPI, PINS, MODE>, CH) {
+        (self.spi, self.channel)
+    }
+}
+
+impl<SPI, PINS, MODE, CH> SpiRxDma<SPI, PINS, MODE, CH> {
+    /// Releases the SPI and the DMA channel
+    pub fn release(self) -> (Spi<SPI, PINS, MODE>, CH) {
+        (self.spi, self.channel)
+    }
+}
+
+impl<SPI, PINS, MODE, RXCH, TXCH> SpiDma<SPI, PINS, MODE, RXCH, TXCH> {
+    /// Releases the SPI and the DMA channels
+    pub fn release(self) -> (Spi<SPI, PINS, MODE>, RXCH, TXCH) {
+        (self.spi, self.rx_channel, self.tx_channel)
+    }
+}
+
+// transmission through DMA needs the data line in output, which is only the
+// case in full duplex and bidirectional modes
+macro_rules! spi_tx_dma {
+    ($SPIX:ident, $TXCH:ty, $MODE:ident) => {
+        impl<PINS> Spi<$SPIX, PINS, $MODE> {
+            /// Uses a DMA channel for transmission
+            pub fn with_tx_dma(self, channel: $TXCH) -> SpiTxDma<$SPIX, PINS, $MODE, $TXCH> {
+                SpiTxDma { spi: self, channel }
+            }
+        }
+
+        impl<PINS> SpiTxDma<$SPIX, PINS, $MODE, $TXCH> {
+            /// Writes all bytes in the buffer using DMA.
+            ///
+            /// Bytes received at the same time are dropped.
+            pub fn write_all(
+                mut self,
+                buffer: &'static [u8],
+            ) -> dma::Transfer<&'static [u8], Self> {
+                let address = self.spi.data_address();
+                setup_channel(
+                    &mut self.channel,
+                    address,
+                    buffer.as_ptr() as u32,
+                    buffer.len(),
+                    Direction::MemoryToPeripheral,
+                );
+                self.spi.clear_overrun();
+                self.channel.start();
+                self.spi.spi.ctl1.modify(|_, w| w.dmaten().set_bit());
+                dma::Transfer::new(buffer, self)
+            }
+        }
+
+        impl<PINS> TransferPayload for SpiTxDma<$SPIX, PINS, $MODE, $TXCH> {
+            fn is_done(&self) -> bool {
+                self.channel.is_complete() && self.spi.is_idle()
+            }
+
+            fn stop(&mut self) {
+                self.channel.stop();
+                self.channel.clear_flags();
+                self.spi.spi.ctl1.modify(|_, w| w.dmaten().clear_bit());
+                self.spi.clear_overrun();
+            }
+        }
+    };
+}
+
+macro_rules! spi_dma {
+    ($($SPIX:ident: ($RXCH:ty, $TXCH:ty),)+) => {
+        $(
+            impl<PINS, MODE> Spi<$SPIX, PINS, MODE> {
+                /// Uses a DMA channel for reception
+                pub fn with_rx_dma(self, channel: $RXCH) -> SpiRxDma<$SPIX, PINS, MODE, $RXCH> {
+                    SpiRxDma { spi: self, channel }
+                }
+
+                #[inline]
+                fn data_address(&self) -> u32 {
+                    &self.spi.data as *const _ as u32
+                }
+
+                #[inline]
+                fn is_idle(&self) -> bool {
+                    let stat = self.spi.stat.read();
+                    stat.tbe().bit_is_set() && stat.trans().bit_is_clear()
+                }
+
+                // drop data received during transmit only DMA transfers, this
+                // also clears the overrun flag
+                #[inline]
+                fn clear_overrun(&mut self) {
+                    let _ = self.spi.data.read();
+                    let _ = self.spi.stat.read();
+                }
+            }
+
+            spi_tx_dma!($SPIX, $TXCH, FullDuplexMode);
+            spi_tx_dma!($SPIX, $TXCH, BidirectionalMode);
+
+            impl<PINS> Spi<$SPIX, PINS, FullDuplexMode> {
+                /// Uses DMA channels for both reception and transmission
+                pub fn with_dma(
+                    self,
+                    rx_channel: $RXCH,
+                    tx_channel: $TXCH,
+                ) -> SpiDma<$SPIX, PINS, FullDuplexMode, $RXCH, $TXCH> {
+                    SpiDma { spi: self, rx_channel, tx_channel }
+                }
+            }
+
+            impl<PINS, MODE> SpiRxDma<$SPIX, PINS, MODE, $RXCH> {
+                #[inline]
+                fn start_read(&mut self, buffer: &mut [u8]) {
+                    let address = self.spi.data_address();
+                    setup_channel(
+                        &mut self.channel,
+                        address,
+                        buffer.as_mut_ptr() as u32,
+                        buffer.len(),
+                        Direction::PeripheralToMemory,
+                    );
+                    self.spi.clear_overrun();
+                    self.channel.start();
+                    self.spi.spi.ctl1.modify(|_, w| w.dmaren().set_bit());
+                }
+            }
+
+            impl<PINS> SpiRxDma<$SPIX, PINS, ReceiveOnlyMode, $RXCH> {
+                /// Reads bytes to fill the buffer using DMA.
+                ///
+                /// The clock is started here and stopped when the transfer is
+                /// waited for, thus extra frames may be clocked out of the device.
+                pub fn read_exact(
+                    mut self,
+                    buffer: &'static mut [u8],
+                ) -> dma::Transfer<&'static mut [u8], Self> {
+                    self.start_read(buffer);
+                    // in receive only mode, enabling the SPI starts the clock
+                    self.spi.spi.ctl0.modify(|_, w| w.spien().set_bit());
+                    dma::Transfer::new(buffer, self)
+                }
+            }
+
+            impl<PINS> TransferPayload for SpiRxDma<$SPIX, PINS, ReceiveOnlyMode, $RXCH> {
+                fn is_done(&self) -> bool {
+                    self.channel.is_complete()
+                }
+
+                fn stop(&mut self) {
+                    self.spi.spi.ctl0.modify(|_, w| w.spien().clear_bit());
+                    self.channel.stop();
+                    self.channel.clear_flags();
+                    self.spi.spi.ctl1.modify(|_, w| w.dmaren().clear_bit());
+                }
+            }
+
+            impl<PINS> SpiRxDma<$SPIX, PINS, BidirectionalMode, $RXCH> {
+                /// Reads bytes from the bidirectional data line using DMA.
+                ///
+                /// The data line is switched into input here, and is switched back
+                /// to output when the transfer is waited for; extra frames may be
+                /// clocked out of the device in between.
+                pub fn read_exact(
+                    mut self,
+                    buffer: &'static mut [u8],
+                ) -> dma::Transfer<&'static mut [u8], Self> {
+                    // wait until last transmission ends before turning around
+                    while !self.spi.is_idle() {}
+                    self.start_read(buffer);
+                    // clearing BDOEN starts the clock in master mode
+                    self.spi.spi.ctl0.modify(|_, w| w.bdoen().clear_bit());
+                    dma::Transfer::new(buffer, self)
+                }
+            }
+
+            impl<PINS> TransferPayload for SpiRxDma<$SPIX, PINS, BidirectionalMode, $RXCH> {
+                fn is_done(&self) -> bool {
+                    self.channel.is_complete()
+                }
+
+                fn stop(&mut self) {
+                    // switch back to output and re-enable the peripheral
+                    self.spi.spi.ctl0.modify(|_, w| w.spien().clear_bit().bdoen().set_bit());
+                    self.spi.spi.ctl0.modify(|_, w| w.spien().set_bit());
+                    self.channel.stop();
+                    self.channel.clear_flags();
+                    self.spi.spi.ctl1.modify(|_, w| w.dmaren().clear_bit());
+                }
+            }
+
+            impl<PINS> SpiDma<$SPIX, PINS, FullDuplexMode, $RXCH, $TXCH> {
+                /// Writes all bytes in the buffer using DMA.
+                ///
+                /// Bytes received at the same time are dropped.
+                pub fn write_all(
+                    mut self,
+                    buffer: &'static [u8],
+                ) -> dma::Transfer<&'static [u8], Self> {
+                    let address = self.spi.data_address();
+                    setup_channel(
+                        &mut self.tx_channel,
+                        address,
+                        buffer.as_ptr() as u32,
+                        buffer.len(),
+                        Direction::MemoryToPeripheral,
+                    );
+                    self.spi.clear_overrun();
+                    self.tx_channel.start();
+                    self.spi.spi.ctl1.modify(|_, w| w.dmaten().set_bit());
+                    dma::Transfer::new(buffer, self)
+                }
+
+                /// Transfers bytes in full duplex using DMA.
+                ///
+                /// Bytes from `tx` are sent while received bytes are written into
+                /// `rx`; both buffers must have the same length.
+                pub fn transfer(
+                    mut self,
+                    tx: &'static [u8],
+                    rx: &'static mut [u8],
+                ) -> dma::Transfer<(&'static [u8], &'static mut [u8]), Self> {
+                    assert_eq!(tx.len(), rx.len(), "buffers must have the same length");
+                    let address = self.spi.data_address();
+                    setup_channel(
+                        &mut self.rx_channel,
+                        address,
+                        rx.as_mut_ptr() as u32,
+                        rx.len(),
+                        Direction::PeripheralToMemory,
+                    );
+                    setup_channel(
+                        &mut self.tx_channel,
+                        address,
+                        tx.as_ptr() as u32,
+                        tx.len(),
+                        Direction::MemoryToPeripheral,
+                    );
+                    self.spi.clear_overrun();
+                    // enable reception before transmission to avoid overrun
+                    self.rx_channel.start();
+                    self.tx_channel.start();
+                    self.spi.spi.ctl1.modify(|_, w| w.dmaren().set_bit());
+                    self.spi.spi.ctl1.modify(|_, w| w.dmaten().set_bit());
+                    dma::Transfer::new((tx, rx), self)
+                }
+            }
+
+            impl<PINS> TransferPayload for SpiDma<$SPIX, PINS, FullDuplexMode, $RXCH, $TXCH> {
+                fn is_done(&self) -> bool {
+                    // the last byte is received after it is sent; reception
+                    // channel is not used if DMAREN is cleared
+                    let rx_done = self.spi.spi.ctl1.read().dmaren().bit_is_clear()
+                        || self.rx_channel.is_complete();
+                    self.tx_channel.is_complete() && self.spi.is_idle() && rx_done
+                }
+
+                fn stop(&mut self) {
+                    self.tx_channel.stop();
+                    self.rx_channel.stop();
+                    self.tx_channel.clear_flags();
+                    self.rx_channel.clear_flags();
+                    self.spi
+                        .spi
+                        .ctl1
+                        .modify(|_, w| w.dmaten().clear_bit().dmaren().clear_bit());
+                    self.spi.clear_overrun();
+                }
+            }
+        )+
+    };
+}
+
+spi_dma! {
+    SPI0: (dma::dma0::C1, dma::dma0::C2),
+    SPI1: (dma::dma0::C3, dma::dma0::C4),
+    SPI2: (dma::dma1::C0, dma::dma1::C1),
 }
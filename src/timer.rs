@@ -1,35 +1,146 @@
 //! Timers
-use crate::pac::TIMER6;
-use crate::rcu::{Clocks, APB1};
-use crate::unit::Hertz;
-use embedded_hal::blocking::delay::DelayMs;
-use embedded_hal::timer::CountDown;
+//!
+//! All basic and general timers, TIMER0 to TIMER6, can be used as count down
+//! timers. TIMER0 is clocked from APB2 and the others from APB1; the timer
+//! clock is twice the APB clock when the APB prescaler is not 1.
+//!
+//! TIMER0 to TIMER4 could be chained: a master timer drives its trigger output
+//! (TRGO), and a slave timer reacts to an internal trigger (ITI) from it.
+//!
+//! TIMER0 to TIMER4 could also count external events as a `Counter`, clocked
+//! from a channel input (external clock mode 0) or the ETI pin (mode 1).
+//!
+//! Update events could request DMA to move values from a buffer into timer
+//! registers, see `TimerDma`; compare events of a channel could request DMA
+//! to update its compare value, see `CompareDma`.
+use crate::afio::PCF0;
+use crate::capture::Edge;
+use crate::dma::{self, Channel as _, Priority, Transfer, TransferPayload, Width};
+use crate::gpio::gpioa::{PA0, PA12, PA15};
+use crate::gpio::gpiod::PD2;
+use crate::gpio::gpioe::{PE0, PE7};
+use crate::gpio::{Floating, Input};
+use crate::pac::afio::pcf0;
+use crate::pac::{TIMER0, TIMER1, TIMER2, TIMER3, TIMER4, TIMER5, TIMER6};
+use crate::pwm::{remap_bits, Ch0, Ch1, Channel, C0, C1, C2, C3};
+use crate::rcu::{Clocks, APB1, APB2};
+use crate::unit::{Hertz, KiloHertz, MegaHertz, MicroSeconds, MilliSeconds};
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use embedded_hal::timer::{CountDown, Periodic};
 use core::convert::Infallible;
+use core::marker::PhantomData;
 
 // I'd prefer using Timer<TIMERx> for convenience
 /// Timer object
 pub struct Timer<TIMER> {
     timer: TIMER,
-    clock_scaler: u16,
     clock_frequency: Hertz,
 }
 
-impl Timer<TIMER6> {
-    /// Initialize the timer.
-    ///
-    /// An enable and reset procedure is procceed to peripheral to clean its state.
-    pub fn timer6(timer: TIMER6, clock: Clocks, apb1: &mut APB1) -> Self {
-        riscv::interrupt::free(|_| {
-            apb1.en().modify(|_, w| w.timer6en().set_bit());
-            apb1.rst().write(|w| w.timer6rst().set_bit());
-            apb1.rst().write(|w| w.timer6rst().clear_bit());
-        });
-        Timer {
-            timer,
-            clock_scaler: 1000,
-            clock_frequency: clock.ck_apb1(),
+/// Timer interrupt events
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// Update event, i.e. the counter reloads after a period
+    Update,
+}
+
+// Flags in INTF are cleared by writing 0 and unchanged by writing 1; other
+// flags are written as 1 so that flags set in the meantime are kept.
+pub(crate) const UPIF: u16 = 1 << 0;
+
+/// Count down period of a timer
+#[derive(Clone, Copy)]
+pub enum Period {
+    /// Time out at the given frequency
+    Frequency(Hertz),
+    /// Time out after the given microseconds
+    MicroSeconds(u32),
+    /// Time out after the given milliseconds
+    MilliSeconds(u32),
+}
+
+impl Period {
+    // number of timer clock cycles in this period, rounded to the nearest
+    pub(crate) fn ticks(self, clock: Hertz) -> u64 {
+        let clock = clock.0 as u64;
+        match self {
+            Period::Frequency(freq) => {
+                let freq = u64::max(freq.0 as u64, 1);
+                (clock + freq / 2) / freq
+            }
+            Period::MicroSeconds(us) => (clock * us as u64 + 500_000) / 1_000_000,
+            Period::MilliSeconds(ms) => (clock * ms as u64 + 500) / 1_000,
+        }
+    }
+}
+
+impl From<Hertz> for Period {
+    fn from(src: Hertz) -> Period {
+        Period::Frequency(src)
+    }
+}
+
+impl From<KiloHertz> for Period {
+    fn from(src: KiloHertz) -> Period {
+        Period::Frequency(src.into())
+    }
+}
+
+impl From<MegaHertz> for Period {
+    fn from(src: MegaHertz) -> Period {
+        Period::Frequency(src.into())
+    }
+}
+
+impl From<MicroSeconds> for Period {
+    fn from(src: MicroSeconds) -> Period {
+        Period::MicroSeconds(src.0)
+    }
+}
+
+impl From<MilliSeconds> for Period {
+    fn from(src: MilliSeconds) -> Period {
+        Period::MilliSeconds(src.0)
+    }
+}
+
+// Prescaler to count at about 1 MHz, i.e. once every microsecond; counts
+// are rounded to be no shorter than a microsecond
+fn us_psc(clock: Hertz) -> u16 {
+    (u32::max(clock.0.div_ceil(1_000_000), 1) - 1) as u16
+}
+
+/// Calculate prescaler and auto-reload register values for a count down of
+/// `ticks` timer clock cycles, with CAR not exceeding `max_car`.
+///
+/// Returns `(psc, car)` where `(psc + 1) * (car + 1)` is closest to `ticks`;
+/// of equally close pairs, the one with the smallest prescaler, thus the
+/// finest resolution, is chosen. Periods out of range are saturated.
+pub fn calc_psc_car(ticks: u64, max_car: u16) -> (u16, u16) {
+    const MAX_DIV: u64 = 1 << 16;
+    let max_cnt = max_car as u64 + 1;
+    if ticks >= MAX_DIV * max_cnt {
+        return (u16::MAX, max_car);
+    }
+    let ticks = u64::max(ticks, 1);
+    // smallest divider to fit the count into CAR
+    let div_min = (ticks - 1) / max_cnt + 1;
+    let (mut best_div, mut best_cnt, mut best_err) = (div_min, 1, u64::MAX);
+    let mut div = div_min;
+    while div <= MAX_DIV && div <= ticks {
+        let cnt = u64::min(u64::max((ticks + div / 2) / div, 1), max_cnt);
+        let err = (div * cnt).abs_diff(ticks);
+        if err < best_err {
+            best_div = div;
+            best_cnt = cnt;
+            best_err = err;
+            if err == 0 {
+                break;
+            }
         }
+        div += 1;
     }
+    ((best_div - 1) as u16, (best_cnt - 1) as u16)
 }
 
 impl<TIMER> Timer<TIMER> {
@@ -43,38 +154,93 @@ impl<TIMER> Timer<TIMER> {
     }
 }
 
-impl<T: Into<u32>> DelayMs<T> for Timer<TIMER6> {
+macro_rules! timers {
+    ($($TIMERX:ident: ($timerx:ident, $timerxen:ident, $timerxrst:ident, $APBX:ident, $ck_timerx:ident),)+) => {
+$(
+impl Timer<$TIMERX> {
+    /// Initialize the timer.
+    ///
+    /// An enable and reset procedure is procceed to peripheral to clean its state.
+    pub fn $timerx(timer: $TIMERX, clock: Clocks, apb: &mut $APBX) -> Self {
+        riscv::interrupt::free(|_| {
+            apb.en().modify(|_, w| w.$timerxen().set_bit());
+            apb.rst().modify(|_, w| w.$timerxrst().set_bit());
+            apb.rst().modify(|_, w| w.$timerxrst().clear_bit());
+        });
+        Timer {
+            timer,
+            clock_frequency: clock.$ck_timerx(),
+        }
+    }
+
+    /// Enables the interrupt of the event
+    pub fn listen(&mut self, event: Event) {
+        match event {
+            Event::Update => self.timer.dmainten.modify(|_, w| w.upie().set_bit()),
+        }
+    }
+
+    /// Disables the interrupt of the event
+    pub fn unlisten(&mut self, event: Event) {
+        match event {
+            Event::Update => self.timer.dmainten.modify(|_, w| w.upie().clear_bit()),
+        }
+    }
+
+    /// Clears the update interrupt flag; call this in the interrupt handler
+    /// or the interrupt would fire again
+    pub fn clear_update_interrupt_flag(&mut self) {
+        self.timer.intf.write(|w| unsafe { w.bits(!UPIF) });
+    }
+
+    /// Checks if the update interrupt flag is set
+    pub fn is_pending(&self) -> bool {
+        self.timer.intf.read().upif().bit_is_set()
+    }
+
+    // Restart counting down from `car` with the prescaler `psc`
+    fn restart(&mut self, psc: u16, car: u16) {
+        riscv::interrupt::free(|_| {
+            self.timer.ctl0.modify(|_, w| w.cen().clear_bit());
+            self.timer.psc.write(|w| unsafe { w.psc().bits(psc) });
+            self.timer.car.modify(|_, w| unsafe { w.carl().bits(car) });
+            // update event loads the prescaler and resets the counter
+            self.timer.intf.write(|w| unsafe { w.bits(!UPIF) });
+            self.timer.swevg.write(|w| w.upg().set_bit());
+            self.timer.intf.write(|w| unsafe { w.bits(!UPIF) });
+            self.timer.ctl0.modify(|_, w| w.cen().set_bit());
+        });
+    }
+}
+
+impl<T: Into<u32>> DelayMs<T> for Timer<$TIMERX> {
     type Error = Infallible;
     fn try_delay_ms(&mut self, ms: T) -> Result<(), Self::Error> {
-        let count = (ms.into() * self.clock_frequency.0) / (self.clock_scaler as u32 * 1000);
-        if count > u16::max_value() as u32 {
-            panic!("can not delay that long");
+        // delay in steps of at most one second, which always fits in
+        // prescaler and auto-reload registers
+        let mut ms = ms.into();
+        while ms > 0 {
+            let step = u32::min(ms, 1000);
+            self.try_start(Period::MilliSeconds(step)).ok();
+            nb::block!(self.try_wait()).ok();
+            ms -= step;
         }
-        self.try_start(count as u16).ok();
-        nb::block!(self.try_wait()).ok();
+        self.timer.ctl0.modify(|_, w| w.cen().clear_bit());
         Ok(())
     }
 }
 
-impl CountDown for Timer<TIMER6> {
+impl CountDown for Timer<$TIMERX> {
     type Error = Infallible;
-    type Time = u16;
+    type Time = Period;
 
-    fn try_start<T>(&mut self, count: T) -> Result<(), Self::Error>
+    fn try_start<T>(&mut self, period: T) -> Result<(), Self::Error>
     where
         T: Into<Self::Time>,
     {
-        let c = count.into();
-        riscv::interrupt::free(|_| {
-            self.timer
-                .psc
-                .write(|w| unsafe { w.psc().bits(self.clock_scaler) });
-            self.timer.intf.write(|w| w.upif().clear_bit());
-            self.timer.swevg.write(|w| w.upg().set_bit());
-            self.timer.intf.write(|w| w.upif().clear_bit());
-            self.timer.car.modify(|_, w| unsafe { w.carl().bits(c) });
-            self.timer.ctl0.modify(|_, w| w.cen().set_bit());
-        });
+        let ticks = period.into().ticks(self.clock_frequency);
+        let (psc, car) = calc_psc_car(ticks, u16::MAX);
+        self.restart(psc, car);
         Ok(())
     }
 
@@ -84,6 +250,8 @@ impl CountDown for Timer<TIMER6> {
     fn try_wait(&mut self) -> nb::Result<(), Self::Error> {
         let flag = self.timer.intf.read().upif().bit_is_set();
         if flag {
+            // clear the flag to wait for the next period
+            self.timer.intf.write(|w| unsafe { w.bits(!UPIF) });
             Ok(())
         } else {
             Err(nb::Error::WouldBlock)
@@ -91,4 +259,816 @@ impl CountDown for Timer<TIMER6> {
     }
 }
 
-// impl Periodic for Timer<TIMER2> {}
+impl<T: Into<u32>> DelayUs<T> for Timer<$TIMERX> {
+    type Error = Infallible;
+    fn try_delay_us(&mut self, us: T) -> Result<(), Self::Error> {
+        // count microseconds, so that each step needs no PSC/CAR search
+        let psc = us_psc(self.clock_frequency);
+        let mut us = us.into();
+        while us > 0 {
+            let step = u32::min(us, 1 << 16);
+            self.restart(psc, (step - 1) as u16);
+            nb::block!(self.try_wait()).ok();
+            us -= step;
+        }
+        self.timer.ctl0.modify(|_, w| w.cen().clear_bit());
+        Ok(())
+    }
+}
+
+// the counter reloads and keeps counting after each update event
+impl Periodic for Timer<$TIMERX> {}
+)+
+    };
+}
+
+timers! {
+    TIMER0: (timer0, timer0en, timer0rst, APB2, ck_timerx),
+    TIMER1: (timer1, timer1en, timer1rst, APB1, ck_timerx_apb1),
+    TIMER2: (timer2, timer2en, timer2rst, APB1, ck_timerx_apb1),
+    TIMER3: (timer3, timer3en, timer3rst, APB1, ck_timerx_apb1),
+    TIMER4: (timer4, timer4en, timer4rst, APB1, ck_timerx_apb1),
+    TIMER5: (timer5, timer5en, timer5rst, APB1, ck_timerx_apb1),
+    TIMER6: (timer6, timer6en, timer6rst, APB1, ck_timerx_apb1),
+}
+
+/// Trigger output (TRGO) source of a master timer (MMC)
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum MasterMode {
+    /// Counter reset by the UPG bit or the slave mode controller
+    Reset,
+    /// Counter enable signal
+    Enable,
+    /// Update event
+    Update,
+    /// Capture or compare match of channel 0
+    ComparePulse,
+    /// Output compare prepare signal of channel 0, O0CPRE
+    Compare0,
+    /// Output compare prepare signal of channel 1, O1CPRE
+    Compare1,
+    /// Output compare prepare signal of channel 2, O2CPRE
+    Compare2,
+    /// Output compare prepare signal of channel 3, O3CPRE
+    Compare3,
+}
+
+impl MasterMode {
+    #[inline]
+    fn bits(self) -> u8 {
+        match self {
+            MasterMode::Reset => 0b000,
+            MasterMode::Enable => 0b001,
+            MasterMode::Update => 0b010,
+            MasterMode::ComparePulse => 0b011,
+            MasterMode::Compare0 => 0b100,
+            MasterMode::Compare1 => 0b101,
+            MasterMode::Compare2 => 0b110,
+            MasterMode::Compare3 => 0b111,
+        }
+    }
+}
+
+/// Reaction of a slave timer to its trigger input (SMC)
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SlaveMode {
+    /// Restart the counter and update registers on the rising edge of the
+    /// trigger
+    Restart,
+    /// Count only while the trigger is high
+    Pause,
+    /// Start the counter on the rising edge of the trigger
+    Event,
+    /// Count rising edges of the trigger (external clock mode 0)
+    ExternalClock,
+}
+
+impl SlaveMode {
+    #[inline]
+    fn bits(self) -> u8 {
+        match self {
+            SlaveMode::Restart => 0b100,
+            SlaveMode::Pause => 0b101,
+            SlaveMode::Event => 0b110,
+            SlaveMode::ExternalClock => 0b111,
+        }
+    }
+}
+
+/// Internal trigger connection from a master timer to this slave timer
+///
+/// Only connections present on the chip are implemented:
+///
+/// | Slave  | ITI0   | ITI1   | ITI2   | ITI3   |
+/// |:-------|:-------|:-------|:-------|:-------|
+/// | TIMER0 | TIMER4 | TIMER1 | TIMER2 | TIMER3 |
+/// | TIMER1 | TIMER0 | -      | TIMER2 | TIMER3 |
+/// | TIMER2 | TIMER0 | TIMER1 | TIMER4 | TIMER3 |
+/// | TIMER3 | TIMER0 | TIMER1 | TIMER2 | -      |
+/// | TIMER4 | TIMER1 | TIMER2 | TIMER3 | -      |
+pub trait InternalTrigger<MASTER> {
+    // TRGS value of the ITI; internal use only
+    #[doc(hidden)]
+    const ITI: u8;
+}
+
+macro_rules! internal_triggers {
+    ($($SLAVE:ident: [$($MASTER:ident: $iti:expr,)+],)+) => {
+        $($(
+            impl InternalTrigger<$MASTER> for $SLAVE {
+                const ITI: u8 = $iti;
+            }
+        )+)+
+    };
+}
+
+internal_triggers! {
+    TIMER0: [TIMER4: 0, TIMER1: 1, TIMER2: 2, TIMER3: 3,],
+    TIMER1: [TIMER0: 0, TIMER2: 2, TIMER3: 3,],
+    TIMER2: [TIMER0: 0, TIMER1: 1, TIMER4: 2, TIMER3: 3,],
+    TIMER3: [TIMER0: 0, TIMER1: 1, TIMER2: 2,],
+    TIMER4: [TIMER1: 0, TIMER2: 1, TIMER3: 2,],
+}
+
+macro_rules! sync_timers {
+    ($($TIMERX:ident,)+) => {
+$(
+impl Timer<$TIMERX> {
+    /// Selects the trigger output (TRGO) of this timer, which could trigger
+    /// slave timers or ADC conversions
+    pub fn set_master_mode(&mut self, mode: MasterMode) {
+        self.timer.ctl1.modify(|_, w| unsafe { w.mmc().bits(mode.bits()) });
+    }
+
+    /// Delays the trigger input by one clock to synchronize this timer
+    /// exactly with its slaves (MSM)
+    pub fn set_master_slave_sync(&mut self, enable: bool) {
+        self.timer.smcfg.modify(|_, w| w.msm().bit(enable));
+    }
+
+    /// Triggers this timer by the trigger output of timer `MASTER`
+    ///
+    /// With `SlaveMode::ExternalClock`, the counter counts trigger events of
+    /// the master; e.g. a slave counting update events of its master extends
+    /// the period to 32 bits.
+    pub fn set_slave_mode<MASTER>(&mut self, mode: SlaveMode)
+    where
+        $TIMERX: InternalTrigger<MASTER>,
+    {
+        let iti = <$TIMERX as InternalTrigger<MASTER>>::ITI;
+        // trigger selection should be set while the slave mode is disabled
+        self.timer.smcfg.modify(|_, w| unsafe { w.smc().bits(0b000) });
+        self.timer.smcfg.modify(|_, w| unsafe { w.trgs().bits(iti) });
+        self.timer.smcfg.modify(|_, w| unsafe { w.smc().bits(mode.bits()) });
+    }
+
+    /// Disables the slave mode; the counter runs from the timer clock
+    pub fn disable_slave_mode(&mut self) {
+        self.timer.smcfg.modify(|_, w| unsafe { w.smc().bits(0b000) });
+    }
+}
+)+
+    };
+}
+
+sync_timers! {
+    TIMER0,
+    TIMER1,
+    TIMER2,
+    TIMER3,
+    TIMER4,
+}
+
+/// External trigger input (ETI) pin of a timer
+///
+/// | Timer  | Remap             | ETI  |
+/// |:-------|:------------------|:-----|
+/// | TIMER0 | No/Partial        | PA12 |
+/// | TIMER0 | Full              | PE7  |
+/// | TIMER1 | No/Partial 2      | PA0  |
+/// | TIMER1 | Partial 1/Full    | PA15 |
+/// | TIMER2 | Any               | PD2  |
+/// | TIMER3 | Any               | PE0  |
+///
+/// TIMER4 has no ETI pin.
+pub trait EtiPin<TIMER> {
+    // (mask, bits) of the remap field this pin requires; internal use only
+    #[doc(hidden)]
+    const REMAP: (u8, u8);
+}
+
+macro_rules! eti_pins {
+    ($($TIMERX:ident: [$($PXi:ident: ($mask:expr, $bits:expr),)+],)+) => {
+        $($(
+            impl EtiPin<$TIMERX> for $PXi<Input<Floating>> {
+                const REMAP: (u8, u8) = ($mask, $bits);
+            }
+        )+)+
+    };
+}
+
+eti_pins! {
+    TIMER0: [PA12: (0b10, 0b00), PE7: (0b11, 0b11),],
+    TIMER1: [PA0: (0b01, 0b00), PA15: (0b01, 0b01),],
+    TIMER2: [PD2: (0, 0),],
+    TIMER3: [PE0: (0, 0),],
+}
+
+/// Prescaler of the external trigger input; counts once every N edges
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum EtiPrescaler {
+    Div1,
+    Div2,
+    Div4,
+    Div8,
+}
+
+/// External trigger input config
+pub struct EtiConfig {
+    /// Counting edge of the ETI pin
+    pub edge: Edge,
+    pub prescaler: EtiPrescaler,
+    /// Digital filter of the input, ETFC, 0 (off) to 15
+    pub filter: u8,
+}
+
+impl Default for EtiConfig {
+    fn default() -> EtiConfig {
+        EtiConfig {
+            edge: Edge::Rising,
+            prescaler: EtiPrescaler::Div1,
+            filter: 0,
+        }
+    }
+}
+
+impl EtiConfig {
+    pub fn edge(mut self, edge: Edge) -> Self {
+        self.edge = edge;
+        self
+    }
+
+    pub fn prescaler(mut self, prescaler: EtiPrescaler) -> Self {
+        self.prescaler = prescaler;
+        self
+    }
+
+    pub fn filter(mut self, filter: u8) -> Self {
+        assert!(filter < 16);
+        self.filter = filter;
+        self
+    }
+
+    #[inline]
+    fn prescaler_bits(&self) -> u8 {
+        match self.prescaler {
+            EtiPrescaler::Div1 => 0b00,
+            EtiPrescaler::Div2 => 0b01,
+            EtiPrescaler::Div4 => 0b10,
+            EtiPrescaler::Div8 => 0b11,
+        }
+    }
+}
+
+/// Event counter clocked from an external pin
+///
+/// The counter counts up to the threshold, then sets the update interrupt
+/// flag and restarts from zero.
+pub struct Counter<TIMER, PIN> {
+    timer: Timer<TIMER>,
+    pin: PIN,
+}
+
+macro_rules! counters {
+    ($($TIMERX:ident: ($set_remap:expr),)+) => {
+$(
+impl Timer<$TIMERX> {
+    /// Counts edges on the channel 0 pin (external clock mode 0 on CI0FE0)
+    pub fn counter_ci0<PIN>(
+        self,
+        pin: PIN,
+        pcf0: &mut PCF0,
+        edge: Edge,
+        filter: u8,
+    ) -> Counter<$TIMERX, PIN>
+    where
+        PIN: Ch0<$TIMERX, Input<Floating>>,
+    {
+        assert!(filter < 16);
+        let remap = remap_bits(&[PIN::REMAP]);
+        riscv::interrupt::free(|_| {
+            pcf0.pcf0().modify(|_, w| {
+                ($set_remap)(w, remap);
+                w
+            });
+        });
+        self.timer.chctl0_input().modify(|_, w| unsafe {
+            w.ch0ms().bits(0b01).ch0capflt().bits(filter)
+        });
+        self.timer.chctl2.modify(|_, w| w.ch0p().bit(edge == Edge::Falling));
+        // slave external clock mode 0, triggered by CI0FE0
+        self.timer.smcfg.write(|w| unsafe { w.trgs().bits(0b101).smc().bits(0b111) });
+        Counter::<$TIMERX, PIN>::init(self, pin)
+    }
+
+    /// Counts edges on the channel 1 pin (external clock mode 0 on CI1FE1)
+    pub fn counter_ci1<PIN>(
+        self,
+        pin: PIN,
+        pcf0: &mut PCF0,
+        edge: Edge,
+        filter: u8,
+    ) -> Counter<$TIMERX, PIN>
+    where
+        PIN: Ch1<$TIMERX, Input<Floating>>,
+    {
+        assert!(filter < 16);
+        let remap = remap_bits(&[PIN::REMAP]);
+        riscv::interrupt::free(|_| {
+            pcf0.pcf0().modify(|_, w| {
+                ($set_remap)(w, remap);
+                w
+            });
+        });
+        self.timer.chctl0_input().modify(|_, w| unsafe {
+            w.ch1ms().bits(0b01).ch1capflt().bits(filter)
+        });
+        self.timer.chctl2.modify(|_, w| w.ch1p().bit(edge == Edge::Falling));
+        // slave external clock mode 0, triggered by CI1FE1
+        self.timer.smcfg.write(|w| unsafe { w.trgs().bits(0b110).smc().bits(0b111) });
+        Counter::<$TIMERX, PIN>::init(self, pin)
+    }
+
+    /// Counts edges on the ETI pin (external clock mode 1)
+    pub fn counter_eti<PIN>(
+        self,
+        pin: PIN,
+        pcf0: &mut PCF0,
+        config: EtiConfig,
+    ) -> Counter<$TIMERX, PIN>
+    where
+        PIN: EtiPin<$TIMERX>,
+    {
+        let remap = remap_bits(&[PIN::REMAP]);
+        riscv::interrupt::free(|_| {
+            pcf0.pcf0().modify(|_, w| {
+                ($set_remap)(w, remap);
+                w
+            });
+        });
+        self.timer.smcfg.write(|w| unsafe {
+            w.etp().bit(config.edge == Edge::Falling)
+                .etpsc().bits(config.prescaler_bits())
+                .etfc().bits(config.filter)
+                .smc1().set_bit()
+        });
+        Counter::<$TIMERX, PIN>::init(self, pin)
+    }
+}
+
+impl<PIN> Counter<$TIMERX, PIN> {
+    // Start counting from zero up to the maximum threshold
+    fn init(timer: Timer<$TIMERX>, pin: PIN) -> Self {
+        riscv::interrupt::free(|_| {
+            timer.timer.psc.write(|w| unsafe { w.psc().bits(0) });
+            timer.timer.car.write(|w| unsafe { w.carl().bits(u16::MAX) });
+            timer.timer.swevg.write(|w| w.upg().set_bit());
+            timer.timer.intf.write(|w| unsafe { w.bits(!UPIF) });
+            timer.timer.ctl0.modify(|_, w| w.cen().set_bit());
+        });
+        Counter { timer, pin }
+    }
+
+    /// Stops counting and returns the timer and the pin
+    pub fn release(self) -> (Timer<$TIMERX>, PIN) {
+        self.timer.timer.ctl0.modify(|_, w| w.cen().clear_bit());
+        self.timer.timer.smcfg.reset();
+        (self.timer, self.pin)
+    }
+
+    /// Returns the count of events since the last reset or threshold
+    pub fn count(&self) -> u16 {
+        self.timer.timer.cnt.read().cnt().bits()
+    }
+
+    /// Resets the count to zero
+    pub fn reset(&mut self) {
+        self.timer.timer.cnt.write(|w| unsafe { w.cnt().bits(0) });
+    }
+
+    /// Sets the count of events after which the counter restarts from zero
+    /// and the threshold flag is set; 0 stands for 65536
+    pub fn set_threshold(&mut self, threshold: u16) {
+        let car = threshold.wrapping_sub(1);
+        self.timer.timer.car.write(|w| unsafe { w.carl().bits(car) });
+    }
+
+    /// Enables the threshold interrupt, i.e. the update interrupt of the timer
+    pub fn listen(&mut self) {
+        self.timer.listen(Event::Update);
+    }
+
+    /// Disables the threshold interrupt
+    pub fn unlisten(&mut self) {
+        self.timer.unlisten(Event::Update);
+    }
+
+    /// Checks if the threshold has been reached
+    pub fn is_threshold_reached(&self) -> bool {
+        self.timer.is_pending()
+    }
+
+    /// Clears the threshold flag; call this in the interrupt handler
+    pub fn clear_threshold_flag(&mut self) {
+        self.timer.clear_update_interrupt_flag();
+    }
+}
+)+
+    };
+}
+
+counters! {
+    TIMER0: (|w: &mut pcf0::W, bits| unsafe { w.timer0_remap().bits(bits); }),
+    TIMER1: (|w: &mut pcf0::W, bits| unsafe { w.timer1_remap().bits(bits); }),
+    TIMER2: (|w: &mut pcf0::W, bits| unsafe { w.timer2_remap().bits(bits); }),
+    TIMER3: (|w: &mut pcf0::W, bits| { w.timer3_remap().bit(bits != 0); }),
+    TIMER4: (|_: &mut pcf0::W, _| {}),
+}
+
+/// DMA requests of a timer
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DmaRequest {
+    /// Request on update events (UPDEN)
+    Update,
+    /// Request on capture or compare events of the channel (CHxDEN)
+    Channel(Channel),
+}
+
+/// First register written by a DMA burst; the burst goes on with the
+/// following registers
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum BurstRegister {
+    /// Prescaler register, PSC
+    Psc,
+    /// Counter auto reload register, CAR
+    Car,
+    /// Counter repetition register, CREP; TIMER0 only
+    Crep,
+    /// Channel 0 capture/compare value register, CH0CV
+    Ch0cv,
+    /// Channel 1 capture/compare value register, CH1CV
+    Ch1cv,
+    /// Channel 2 capture/compare value register, CH2CV
+    Ch2cv,
+    /// Channel 3 capture/compare value register, CH3CV
+    Ch3cv,
+}
+
+impl BurstRegister {
+    // DMATA value: register offset from CTL0 in words
+    #[inline]
+    fn bits(self) -> u8 {
+        match self {
+            BurstRegister::Psc => 10,
+            BurstRegister::Car => 11,
+            BurstRegister::Crep => 12,
+            BurstRegister::Ch0cv => 13,
+            BurstRegister::Ch1cv => 14,
+            BurstRegister::Ch2cv => 15,
+            BurstRegister::Ch3cv => 16,
+        }
+    }
+}
+
+/// Timer with the DMA channel serving its update requests
+///
+/// Each update event moves one value, or one burst of values, from the
+/// buffer into timer registers; e.g. with the timer running PWM, a buffer of
+/// compare values generates an arbitrary waveform.
+pub struct TimerDma<TIMER, CH> {
+    timer: Timer<TIMER>,
+    channel: CH,
+}
+
+impl<TIMER, CH> TimerDma<TIMER, CH> {
+    /// Releases the timer and the DMA channel
+    pub fn release(self) -> (Timer<TIMER>, CH) {
+        (self.timer, self.channel)
+    }
+}
+
+/// DMA channel serving the capture/compare requests (CHxDEN) of channel `CH`
+/// of a timer
+///
+/// | Timer  | CH0      | CH1      | CH2      | CH3      |
+/// |:-------|:---------|:---------|:---------|:---------|
+/// | TIMER0 | DMA0 CH1 | DMA0 CH2 | DMA0 CH5 | DMA0 CH3 |
+/// | TIMER1 | DMA0 CH4 | DMA0 CH6 | DMA0 CH0 | DMA0 CH6 |
+/// | TIMER2 | DMA0 CH5 | -        | DMA0 CH1 | DMA0 CH2 |
+/// | TIMER3 | DMA0 CH0 | DMA0 CH3 | DMA0 CH4 | -        |
+/// | TIMER4 | DMA1 CH4 | DMA1 CH3 | DMA1 CH1 | DMA1 CH0 |
+pub trait CompareDmaChannel<TIMER, CH>: dma::Channel {}
+
+macro_rules! compare_dma_channels {
+    ($($TIMERX:ident: [$($CX:ident: $DMACH:ty,)+],)+) => {
+        $($(
+            impl CompareDmaChannel<$TIMERX, $CX> for $DMACH {}
+        )+)+
+    };
+}
+
+compare_dma_channels! {
+    TIMER0: [C0: dma::dma0::C1, C1: dma::dma0::C2, C2: dma::dma0::C5, C3: dma::dma0::C3,],
+    TIMER1: [C0: dma::dma0::C4, C1: dma::dma0::C6, C2: dma::dma0::C0, C3: dma::dma0::C6,],
+    TIMER2: [C0: dma::dma0::C5, C2: dma::dma0::C1, C3: dma::dma0::C2,],
+    TIMER3: [C0: dma::dma0::C0, C1: dma::dma0::C3, C2: dma::dma0::C4,],
+    TIMER4: [C0: dma::dma1::C4, C1: dma::dma1::C3, C2: dma::dma1::C1, C3: dma::dma1::C0,],
+}
+
+/// Timer with the DMA channel serving compare requests of its channel `CH`
+///
+/// Each compare match of the channel moves the next value of the buffer into
+/// its compare value register.
+pub struct CompareDma<TIMER, CH, DMACH> {
+    timer: Timer<TIMER>,
+    channel: DMACH,
+    _channel: PhantomData<CH>,
+}
+
+impl<TIMER, CH, DMACH> CompareDma<TIMER, CH, DMACH> {
+    /// Releases the timer and the DMA channel
+    pub fn release(self) -> (Timer<TIMER>, DMACH) {
+        (self.timer, self.channel)
+    }
+}
+
+// Prepare a channel to move half words from memory into a timer register on
+// each request; the caller starts the channel afterwards.
+fn setup_timer_channel<CH: dma::Channel>(channel: &mut CH, address: u32, buffer: &[u16]) {
+    assert!(buffer.len() <= u16::MAX as usize, "buffer too long for DMA");
+    channel.stop();
+    channel.clear_flags();
+    channel.set_peripheral_address(address, Width::Bits16);
+    channel.set_memory_address(buffer.as_ptr() as u32, Width::Bits16, true);
+    channel.set_transfer_length(buffer.len() as u16);
+    // requests come at a fixed rate and should not wait for other channels
+    channel.set_direction(dma::Direction::MemoryToPeripheral, Priority::High);
+    dma::start_fence();
+}
+
+macro_rules! timer_dma {
+    ($($TIMERX:ident: ($UPCH:ty),)+) => {
+$(
+impl Timer<$TIMERX> {
+    /// Uses the DMA channel of update requests
+    pub fn with_update_dma(self, channel: $UPCH) -> TimerDma<$TIMERX, $UPCH> {
+        TimerDma {
+            timer: self,
+            channel,
+        }
+    }
+}
+
+impl TimerDma<$TIMERX, $UPCH> {
+    // Start the channel and request DMA on update events
+    fn start(mut self, address: u32, buffer: &'static [u16]) -> Transfer<&'static [u16], Self> {
+        setup_timer_channel(&mut self.channel, address, buffer);
+        self.channel.start();
+        self.timer.timer.dmainten.modify(|_, w| w.upden().set_bit());
+        Transfer::new(buffer, self)
+    }
+
+    /// Writes one value of the buffer into the auto reload register on each
+    /// update event, changing the period of each following cycle
+    pub fn write_auto_reload(
+        self,
+        buffer: &'static [u16],
+    ) -> Transfer<&'static [u16], Self> {
+        let address = &self.timer.timer.car as *const _ as u32;
+        self.start(address, buffer)
+    }
+}
+
+impl TransferPayload for TimerDma<$TIMERX, $UPCH> {
+    fn is_done(&self) -> bool {
+        self.channel.is_complete()
+    }
+
+    fn stop(&mut self) {
+        self.timer.timer.dmainten.modify(|_, w| w.upden().clear_bit());
+        self.channel.stop();
+        self.channel.clear_flags();
+    }
+}
+)+
+    };
+}
+
+timer_dma! {
+    TIMER0: (dma::dma0::C4),
+    TIMER1: (dma::dma0::C1),
+    TIMER2: (dma::dma0::C2),
+    TIMER3: (dma::dma0::C6),
+    TIMER4: (dma::dma1::C1),
+    TIMER5: (dma::dma1::C2),
+    TIMER6: (dma::dma1::C3),
+}
+
+macro_rules! timer_dma_general {
+    ($($TIMERX:ident: ($UPCH:ty),)+) => {
+$(
+impl Timer<$TIMERX> {
+    /// Enables a DMA request; the DMA channel should be programmed by the
+    /// caller, see the DMA request table in the User Manual. `TimerDma` and
+    /// `CompareDma` enable their requests by themselves.
+    pub fn enable_dma(&mut self, request: DmaRequest) {
+        self.timer.dmainten.modify(|_, w| match request {
+            DmaRequest::Update => w.upden().set_bit(),
+            DmaRequest::Channel(Channel::C0) => w.ch0den().set_bit(),
+            DmaRequest::Channel(Channel::C1) => w.ch1den().set_bit(),
+            DmaRequest::Channel(Channel::C2) => w.ch2den().set_bit(),
+            DmaRequest::Channel(Channel::C3) => w.ch3den().set_bit(),
+        });
+    }
+
+    /// Disables a DMA request
+    pub fn disable_dma(&mut self, request: DmaRequest) {
+        self.timer.dmainten.modify(|_, w| match request {
+            DmaRequest::Update => w.upden().clear_bit(),
+            DmaRequest::Channel(Channel::C0) => w.ch0den().clear_bit(),
+            DmaRequest::Channel(Channel::C1) => w.ch1den().clear_bit(),
+            DmaRequest::Channel(Channel::C2) => w.ch2den().clear_bit(),
+            DmaRequest::Channel(Channel::C3) => w.ch3den().clear_bit(),
+        });
+    }
+}
+
+impl Timer<$TIMERX> {
+    /// Uses the DMA channel of compare requests of the timer channel
+    pub fn with_compare_dma<CH, DMACH>(
+        self,
+        _channel: CH,
+        dma_channel: DMACH,
+    ) -> CompareDma<$TIMERX, CH, DMACH>
+    where
+        DMACH: CompareDmaChannel<$TIMERX, CH>,
+    {
+        CompareDma {
+            timer: self,
+            channel: dma_channel,
+            _channel: PhantomData,
+        }
+    }
+}
+
+impl TimerDma<$TIMERX, $UPCH> {
+    /// Writes one value of the buffer into the compare value register of
+    /// the channel on each update event
+    pub fn write_compare(
+        self,
+        channel: Channel,
+        buffer: &'static [u16],
+    ) -> Transfer<&'static [u16], Self> {
+        let timer = &self.timer.timer;
+        let address = match channel {
+            Channel::C0 => &timer.ch0cv as *const _ as u32,
+            Channel::C1 => &timer.ch1cv as *const _ as u32,
+            Channel::C2 => &timer.ch2cv as *const _ as u32,
+            Channel::C3 => &timer.ch3cv as *const _ as u32,
+        };
+        self.start(address, buffer)
+    }
+
+    /// Writes `count` consecutive registers from `first` on each update
+    /// event, through the DMA transfer buffer register (DMATB).
+    ///
+    /// The buffer holds `count` values for each update event.
+    pub fn write_burst(
+        self,
+        first: BurstRegister,
+        count: u8,
+        buffer: &'static [u16],
+    ) -> Transfer<&'static [u16], Self> {
+        assert!(count >= 1 && first.bits() + count <= 18, "burst out of range");
+        assert!(buffer.len() % count as usize == 0);
+        self.timer.timer.dmacfg.write(|w| unsafe {
+            w.dmata().bits(first.bits()).dmatc().bits(count - 1)
+        });
+        let address = &self.timer.timer.dmatb as *const _ as u32;
+        self.start(address, buffer)
+    }
+}
+)+
+    };
+}
+
+timer_dma_general! {
+    TIMER0: (dma::dma0::C4),
+    TIMER1: (dma::dma0::C1),
+    TIMER2: (dma::dma0::C2),
+    TIMER3: (dma::dma0::C6),
+    TIMER4: (dma::dma1::C1),
+}
+
+macro_rules! compare_dma {
+    ($($TIMERX:ident: [$($CX:ident: ($chxcv:ident, $chxden:ident),)+],)+) => {
+        $($(
+            impl<DMACH: CompareDmaChannel<$TIMERX, $CX>> CompareDma<$TIMERX, $CX, DMACH> {
+                /// Writes the next value of the buffer into the compare value
+                /// register on each compare match of the channel
+                pub fn write_compare(
+                    mut self,
+                    buffer: &'static [u16],
+                ) -> Transfer<&'static [u16], Self> {
+                    let address = &self.timer.timer.$chxcv as *const _ as u32;
+                    setup_timer_channel(&mut self.channel, address, buffer);
+                    self.channel.start();
+                    self.timer.timer.dmainten.modify(|_, w| w.$chxden().set_bit());
+                    Transfer::new(buffer, self)
+                }
+            }
+
+            impl<DMACH: CompareDmaChannel<$TIMERX, $CX>> TransferPayload
+                for CompareDma<$TIMERX, $CX, DMACH>
+            {
+                fn is_done(&self) -> bool {
+                    self.channel.is_complete()
+                }
+
+                fn stop(&mut self) {
+                    self.timer.timer.dmainten.modify(|_, w| w.$chxden().clear_bit());
+                    self.channel.stop();
+                    self.channel.clear_flags();
+                }
+            }
+        )+)+
+    };
+}
+
+compare_dma! {
+    TIMER0: [C0: (ch0cv, ch0den), C1: (ch1cv, ch1den), C2: (ch2cv, ch2den), C3: (ch3cv, ch3den),],
+    TIMER1: [C0: (ch0cv, ch0den), C1: (ch1cv, ch1den), C2: (ch2cv, ch2den), C3: (ch3cv, ch3den),],
+    TIMER2: [C0: (ch0cv, ch0den), C2: (ch2cv, ch2den), C3: (ch3cv, ch3den),],
+    TIMER3: [C0: (ch0cv, ch0den), C1: (ch1cv, ch1den), C2: (ch2cv, ch2den),],
+    TIMER4: [C0: (ch0cv, ch0den), C1: (ch1cv, ch1den), C2: (ch2cv, ch2den), C3: (ch3cv, ch3den),],
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Checks that the pair counts exactly `ticks` timer clock cycles
+    fn exact(ticks: u64, max_car: u16) -> (u16, u16) {
+        let (psc, car) = calc_psc_car(ticks, max_car);
+        assert_eq!((psc as u64 + 1) * (car as u64 + 1), ticks);
+        (psc, car)
+    }
+
+    #[test]
+    fn psc_car_without_prescaler() {
+        assert_eq!(calc_psc_car(0, u16::MAX), (0, 0));
+        assert_eq!(exact(1, u16::MAX), (0, 0));
+        assert_eq!(exact(1000, u16::MAX), (0, 999));
+        assert_eq!(exact(65536, u16::MAX), (0, 65535));
+    }
+
+    #[test]
+    fn psc_car_with_prescaler() {
+        assert_eq!(exact(131_072, u16::MAX), (1, 65535));
+        // one second at 108 MHz, 72 MHz and 48 MHz
+        assert_eq!(exact(108_000_000, u16::MAX), (1727, 62499));
+        assert_eq!(exact(72_000_000, u16::MAX), (1124, 63999));
+        assert_eq!(exact(48_000_000, u16::MAX), (749, 63999));
+    }
+
+    #[test]
+    fn psc_car_nearest() {
+        // 65537 is a prime; the smallest prescaler of the nearest pairs
+        assert_eq!(calc_psc_car(65537, u16::MAX), (1, 32768));
+        // 2^32 - 1 only fits with the largest prescaler
+        assert_eq!(calc_psc_car((1 << 32) - 1, u16::MAX), (65535, 65535));
+    }
+
+    #[test]
+    fn psc_car_limited_car() {
+        assert_eq!(exact(65535, 0xFFFE), (0, 65534));
+        assert_eq!(exact(65536, 0xFFFE), (1, 32767));
+        assert_eq!(exact(108_000_000, 0xFFFE), (1727, 62499));
+    }
+
+    #[test]
+    fn microsecond_prescaler() {
+        assert_eq!(us_psc(Hertz(108_000_000)), 107);
+        assert_eq!(us_psc(Hertz(8_000_000)), 7);
+        // rounded to counts longer than a microsecond
+        assert_eq!(us_psc(Hertz(54_000_001)), 54);
+        assert_eq!(us_psc(Hertz(500_000)), 0);
+    }
+
+    #[test]
+    fn psc_car_saturated() {
+        assert_eq!(calc_psc_car(1 << 32, u16::MAX), (65535, 65535));
+        assert_eq!(calc_psc_car(u64::MAX, u16::MAX), (65535, 65535));
+        assert_eq!(calc_psc_car(65536 * 65535, 0xFFFE), (65535, 65534));
+    }
+}
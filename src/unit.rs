@@ -1,15 +1,15 @@
 //! Measurement units
 
 /// Hertz
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct Hertz(pub u32);
 
 /// Kilo hertz
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct KiloHertz(pub u32);
 
 /// Mega hertz
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct MegaHertz(pub u32);
 
 /// Extension trait that add convenient methods to the `u32` type
@@ -73,10 +73,12 @@ impl Into<KiloHertz> for MegaHertz {
 }
 
 /// Milliseconds
+#[derive(PartialEq, Eq)]
 pub struct MilliSeconds(pub u32);
 
 // todo: there's no need for accurate time units by now
 /// Microseconds
+#[derive(PartialEq, Eq)]
 pub struct MicroSeconds(pub u32);
 
 impl Into<MicroSeconds> for MilliSeconds {
@@ -86,4 +88,5 @@ impl Into<MicroSeconds> for MilliSeconds {
 }
 
 /// Bits per second
+#[derive(PartialEq, Eq)]
 pub struct Bps(pub u32);